@@ -1,6 +1,7 @@
 use std::mem;
 
 use interface::text;
+use network_common::PlayerId;
 
 use crate::{
     border::BorderRenderer,
@@ -16,6 +17,8 @@ struct Message {
 pub struct ChatBox<'a> {
     pub message_ready: bool,
     pub message_buffer: String,
+    /// The player (and their name) that the message being typed is only sent to
+    pub recipient: Option<(PlayerId, Vec<u8>)>,
     messages: Vec<Message>,
 
     font: &'a Font,
@@ -37,6 +40,7 @@ impl<'a> ChatBox<'a> {
         ChatBox {
             message_ready: false,
             message_buffer: String::new(),
+            recipient: None,
             messages: Vec::new(),
 
             font,
@@ -63,7 +67,13 @@ impl<'a> ChatBox<'a> {
         let msg_padding = self.font.char_width() / 2;
 
         // Draw the text that the player is currently typing
-        let encoded_buffer: Vec<_> = text::Encoder::new(&self.message_buffer).collect();
+        let mut encoded_buffer = vec![];
+        if let Some((_, name)) = &self.recipient {
+            encoded_buffer.extend(text::Encoder::new("TO "));
+            encoded_buffer.extend(name);
+            encoded_buffer.extend(text::Encoder::new(": "));
+        }
+        encoded_buffer.extend(text::Encoder::new(&self.message_buffer));
         y += draw_text(renderer, &self.font, &encoded_buffer, &self.inner_rect);
         y += self.font.line_height();
 
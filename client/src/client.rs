@@ -10,7 +10,10 @@ use macroquad::{
     window::{next_frame, request_new_screen_size},
 };
 
-use crate::{border::BorderRenderer, common::Renderer, font::Font, game::Game, net::ClientManager};
+use crate::{
+    border::BorderRenderer, common::Renderer, font::Font, game::Game, net::ClientManager,
+    save::Cartridge,
+};
 
 const EMU_SCALE: u32 = 3;
 pub const EMU_WIDTH: u32 = graphics::WIDTH as u32 * EMU_SCALE;
@@ -64,6 +67,7 @@ impl<'a> EventHandler for Game<'a> {
 pub async fn run(
     mut client_manager: ClientManager,
     emulator: Box<Emulator>,
+    cartridge: Cartridge,
 ) -> Result<(), Box<dyn Error>> {
     request_new_screen_size(EMU_WIDTH as f32, EMU_HEIGHT as f32);
 
@@ -71,7 +75,7 @@ pub async fn run(
     let font_data = load_font(&renderer, &emulator.mem);
     let border_renderer = load_border_renderer(&renderer, &emulator.mem);
 
-    let mut game = Game::new(emulator, &font_data, &border_renderer);
+    let mut game = Game::new(emulator, cartridge, &font_data, &border_renderer);
 
     let mut prev_time = Instant::now();
     let mut frame_time = 0;
@@ -120,8 +124,8 @@ fn load_font(_renderer: &Renderer, mem: &Memory) -> Font {
     // Extract the font data from the game
     let data = extract::extract_texture(
         mem,
-        interface::offsets::FONT_BANK,
-        interface::offsets::FONT_ADDR,
+        interface::offsets::get().font_bank,
+        interface::offsets::get().font_addr,
         FONT_TEX_WIDTH,
         FONT_TEX_HEIGHT,
         extract::TextureFormat::Bpp1,
@@ -141,8 +145,8 @@ fn load_border_renderer(_renderer: &Renderer, mem: &Memory) -> BorderRenderer {
     // Extract the border data from the game
     let data = extract::extract_texture(
         mem,
-        interface::offsets::FONT_BANK,
-        interface::offsets::BORDER_ADDR,
+        interface::offsets::get().font_bank,
+        interface::offsets::get().border_addr,
         BORDER_TEX_WIDTH,
         BORDER_TEX_HEIGHT,
         extract::TextureFormat::Bpp2,
@@ -31,39 +31,60 @@ impl Font {
     }
 }
 
+/// The font only has the characters from 0x80 onwards, so the few printable characters before that
+/// are drawn with similar characters from the font instead
+fn replacement(char_: u8) -> Option<&'static [u8]> {
+    match char_ {
+        // <PKMN> is drawn as <PK><MN>
+        0x4A => Some(&[0xE1, 0xE2]),
+        // Quotes
+        0x70..=0x73 => Some(&[0xE0]),
+        // ・ and …
+        0x74 => Some(&[0xF2]),
+        0x75 => Some(&[0xE8, 0xE8, 0xE8]),
+        // Box drawing characters
+        0x7A => Some(&[0xE3]),
+        0x79..=0x7E => Some(&[special::SPACE]),
+        _ => None,
+    }
+}
+
 /// Draw text, returning the total height of the text drawn
 pub fn draw_text(renderer: &mut Renderer, font: &Font, text: &[u8], target: &Rect) -> i32 {
     let (mut x, mut y) = (target.x, target.y);
-    for &char_ in text {
-        match char_ {
-            // These are all control characters, and so do not matter when we are manually rendering
-            // the text
-            special::TEXT_START | special::SCROLL_LINE | special::END_MSG | special::END_PROMPT => {
-            }
+    'text: for &value in text {
+        let chars = replacement(value).unwrap_or(std::slice::from_ref(&value));
 
-            special::SPACE => x += font.char_width(),
+        for &char_ in chars {
+            match char_ {
+                special::SPACE => x += font.char_width(),
 
-            special::LINE_DOWN => {
-                x = target.x;
-                y += font.line_height();
-            }
+                special::LINE_DOWN => {
+                    x = target.x;
+                    y += font.line_height();
+                }
 
-            special::TERMINATOR => break,
+                special::TERMINATOR => break 'text,
 
-            normal_char => {
-                // The index of normal characters in the font is their value - 0x80
-                font.draw_char(renderer, (normal_char - 0x80) as i32, x, y);
-                x += font.char_width();
+                // The rest are all control characters, and so do not matter when we are manually
+                // rendering the text
+                0x00..=0x7F => {}
+
+                normal_char => {
+                    // The index of normal characters in the font is their value - 0x80
+                    font.draw_char(renderer, (normal_char - 0x80) as i32, x, y);
+                    x += font.char_width();
+                }
             }
-        }
 
-        // Check for wrapping
-        if x - target.x + font.char_width() > target.width {
-            x = target.x;
-            y += font.line_height();
-        }
-        if y - target.y + font.line_height() > target.height {
-            break;
+            // Check for wrapping
+            if x - target.x + font.char_width() > target.width {
+                x = target.x;
+                y += font.line_height();
+            }
+            if y - target.y + font.line_height() > target.height {
+                break 'text;
+            }
         }
     }
 
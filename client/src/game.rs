@@ -1,18 +1,19 @@
-use std::{cell::RefCell, mem};
+use std::{cell::RefCell, fs, mem};
 
 use gb_emu::{cpu::Cpu, emulator::Emulator, graphics, joypad, mmu::Memory};
 
 use interface::{
     self,
-    data::{PlayerData, SpriteData},
-    extract, hacks,
-    values::Direction,
-    InterfaceData, InterfaceState,
+    data::{MapHeader, NameTables, Party, PlayerData, SpriteData, MAX_GREETING_LENGTH},
+    extract, hacks, offsets, text,
+    values::{self, Direction},
+    Dialog, InterfaceData, InterfaceState, NetworkRequest, PlayerId,
 };
 use macroquad::{
     prelude::{KeyCode, WHITE},
     texture::{render_target, FilterMode, Image, Texture2D},
 };
+use network_common::ServerRules;
 
 use crate::{
     border::BorderRenderer,
@@ -21,17 +22,68 @@ use crate::{
     common::{Rect, Renderer},
     font::Font,
     menu::ItemBox,
+    party::PartyViewer,
+    prompt::PromptBox,
+    save::{self, Backup, Cartridge},
 };
 
+/// Where the player's greeting is kept between sessions
+const GREETING_PATH: &str = "greeting.txt";
+
+/// The position of the `RESTORE SAVE` item in the menu
+const RESTORE_SAVE_ITEM: usize = 2;
+
 #[derive(PartialEq, Eq)]
 pub enum GameState {
     Emulator,
     ChatBox,
     Menu,
+    /// Choosing a backup of the save file to restore
+    RestoreMenu,
+    Prompt,
+    /// Looking at the party of another player
+    PartyView,
+}
+
+/// The things that can be done with a player after talking to them
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum Interaction {
+    Battle,
+    Trade,
+    ViewParty,
+    Chat,
+    Cancel,
+}
+
+/// What the prompt that is currently being shown to the player is asking about
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum PromptKind {
+    /// Another player has challenged us to a battle
+    BattleChallenge(PlayerId),
+    /// Choose which pokemon to offer in a trade with another player
+    TradeOffer(PlayerId),
+    /// Both players have offered a pokemon, and the trade needs to be confirmed
+    TradeConfirm(PlayerId),
+    /// Choose what to do with a player that we talked to
+    Interaction(PlayerId),
+}
+
+impl PromptKind {
+    /// The player whose request the prompt is answering. The interaction menu is opened by
+    /// talking to a player, so it is not answering anything that they could withdraw.
+    pub fn request_from(&self) -> Option<PlayerId> {
+        match *self {
+            PromptKind::BattleChallenge(id)
+            | PromptKind::TradeOffer(id)
+            | PromptKind::TradeConfirm(id) => Some(id),
+            PromptKind::Interaction(_) => None,
+        }
+    }
 }
 
 pub struct Game<'a> {
     pub emulator: Box<Emulator>,
+    pub cartridge: Cartridge,
     pub screen: Image,
     pub screen_texture: Texture2D,
     pub font: &'a Font,
@@ -41,7 +93,15 @@ pub struct Game<'a> {
     pub interface_data: RefCell<InterfaceData>,
     pub chat_box: ChatBox<'a>,
     pub menu: ItemBox<'a>,
+    pub restore_menu: Option<(Vec<Backup>, ItemBox<'a>)>,
+    pub prompt: Option<(PromptKind, PromptBox<'a>)>,
+    pub party_viewer: Option<PartyViewer<'a>>,
     pub player_data: PlayerData,
+    /// The header of the map that the player is currently on
+    pub map_header: Option<MapHeader>,
+    pub rules: ServerRules,
+    /// The names of species, moves and items, read from the rom
+    pub names: NameTables,
     pub fast_mode: bool,
     pub exit_requested: bool,
 }
@@ -49,10 +109,13 @@ pub struct Game<'a> {
 impl<'a> Game<'a> {
     pub fn new(
         emulator: Box<Emulator>,
+        cartridge: Cartridge,
         font: &'a Font,
         border_renderer: &'a BorderRenderer,
     ) -> Game<'a> {
-        let player_data = PlayerData::new(&emulator.mem);
+        let names = extract::name_tables(&emulator.mem);
+        let mut player_data = PlayerData::new(&emulator.mem);
+        player_data.greeting = fs::read_to_string(GREETING_PATH).unwrap_or_default();
 
         let chat_box_rect = Rect::new(
             client::EMU_WIDTH as i32,
@@ -72,6 +135,7 @@ impl<'a> Game<'a> {
 
         Game {
             emulator,
+            cartridge,
             screen: Image::gen_image_color(graphics::WIDTH as u16, graphics::HEIGHT as u16, WHITE),
             screen_texture,
             font,
@@ -81,23 +145,41 @@ impl<'a> Game<'a> {
             interface_data: RefCell::new(InterfaceData::new()),
             chat_box: ChatBox::new(font, border_renderer, chat_box_rect),
             menu: ItemBox::new(
-                vec!["CONNECT".to_string(), "SHOW PLAYERS".to_string(), "EXIT".to_string()],
+                vec![
+                    "CONNECT".to_string(),
+                    "SHOW PLAYERS".to_string(),
+                    "RESTORE SAVE".to_string(),
+                    "EXIT".to_string(),
+                ],
                 font,
                 border_renderer,
                 menu_rect,
             ),
+            restore_menu: None,
+            prompt: None,
+            party_viewer: None,
             player_data,
+            map_header: None,
+            rules: ServerRules::default(),
+            names,
             fast_mode: false,
             exit_requested: false,
         }
     }
 
     pub fn update(&mut self) {
+        // The game is paused while it waits for the other end of the link cable, so that the
+        // network latency is hidden from it.
+        if self.interface_data.borrow_mut().link_waiting(&mut self.emulator.mem) {
+            return;
+        }
+
         if self.interface_data.borrow().state == InterfaceState::Normal {
             // Individually borrow elements of self that we need so that we pass Rust's borrow
             // checker. (Hopefully we won't need to do this in the future)
             let interface_data = &mut self.interface_data;
             let player_data = &mut self.player_data;
+            let map_header = &mut self.map_header;
             let screen = &mut self.screen;
             let emulator = &mut self.emulator;
 
@@ -107,6 +189,8 @@ impl<'a> Game<'a> {
                 let interface_data = &mut interface_data.borrow_mut();
                 hacks::sprite_check(cpu, mem, interface_data);
                 hacks::display_text(cpu, mem, interface_data);
+                hacks::link_battle(cpu, mem, interface_data);
+                hacks::link_cable(mem, interface_data);
                 hacks::sprite_update_tracker(cpu, mem, interface_data);
             };
 
@@ -118,12 +202,19 @@ impl<'a> Game<'a> {
                     name: extract::player_name(mem),
                     sprite: mem::replace(&mut player_data.sprite, vec![]),
                     movement_data: extract::movement_data(mem),
+                    greeting: mem::take(&mut player_data.greeting),
                 };
                 *player_data = new_player_data;
 
+                let map_id = player_data.movement_data.map_id;
+                if map_header.as_ref().map(|map| map.map_id) != Some(map_id) {
+                    *map_header = Some(extract::map_header(mem, map_id));
+                }
+
                 let interface_data = &interface_data.borrow();
                 if interface_data.sprites_enabled() {
-                    draw_other_players(interface_data, player_data, mem);
+                    let map_header = map_header.as_ref().unwrap();
+                    draw_other_players(interface_data, player_data, map_header, mem);
                 }
 
                 screen.bytes.copy_from_slice(&mem.gpu.framebuffer);
@@ -132,6 +223,16 @@ impl<'a> Game<'a> {
 
             emulator.frame(on_tick, on_vblank);
         }
+
+        let dialog = self.interface_data.borrow_mut().new_dialog.take();
+        match dialog {
+            Some(Dialog::Interaction(id)) => self.show_interaction_menu(id),
+            // The request itself has been written in the game's message box
+            Some(Dialog::Request(id)) => {
+                self.show_choices(PromptKind::BattleChallenge(id), yes_no());
+            }
+            None => {}
+        }
     }
 
     pub fn render(&self, renderer: &mut Renderer) {
@@ -145,13 +246,179 @@ impl<'a> Game<'a> {
         if self.game_state == GameState::Menu {
             self.menu.draw(renderer);
         }
+
+        if let (GameState::RestoreMenu, Some((_, menu))) = (&self.game_state, &self.restore_menu) {
+            menu.draw(renderer);
+        }
+
+        if let Some((_, prompt)) = &self.prompt {
+            prompt.draw(renderer);
+        }
+        if let Some(party_viewer) = &self.party_viewer {
+            party_viewer.draw(renderer);
+        }
+    }
+
+    /// Check if the player is in the middle of something that should not be interrupted by
+    /// requests from other players
+    pub fn is_busy(&self) -> bool {
+        let interface_data = self.interface_data.borrow();
+        self.game_state != GameState::Emulator
+            || interface_data.state != InterfaceState::Normal
+            || interface_data.has_request()
+            || self.emulator.mem.lb(offsets::get().active_battle)
+                != values::ActiveBattle::None as u8
+    }
+
+    /// Ask the player to choose from a list of options, the answer is handled by `answer_prompt`
+    pub fn show_prompt(&mut self, kind: PromptKind, message: Vec<u8>, choices: Vec<Vec<u8>>) {
+        self.show_prompt_box(kind, Some(message), choices);
+    }
+
+    /// Show a list of options over the game's message box, which already contains the question
+    pub fn show_choices(&mut self, kind: PromptKind, choices: Vec<Vec<u8>>) {
+        self.show_prompt_box(kind, None, choices);
+    }
+
+    fn show_prompt_box(
+        &mut self,
+        kind: PromptKind,
+        message: Option<Vec<u8>>,
+        choices: Vec<Vec<u8>>,
+    ) {
+        const PROMPT_LINES: i32 = 6;
+
+        let height = PROMPT_LINES * self.font.line_height();
+        let rect = Rect::new(
+            0,
+            client::EMU_HEIGHT as i32 - height,
+            client::EMU_WIDTH as i32,
+            height,
+        );
+        let prompt = PromptBox::new(message, choices, self.font, self.border_renderer, rect);
+
+        self.prompt = Some((kind, prompt));
+        self.game_state = GameState::Prompt;
+
+        // The player may be holding down a button when the prompt appears, and the game will
+        // not see it being released.
+        self.release_joypad();
+    }
+
+    /// Show a summary of another player's party over the game
+    pub fn show_party(&mut self, id: PlayerId, party: &Party) {
+        let owner = match self.interface_data.borrow().players.get(&id) {
+            Some(player) => player.name.clone(),
+            None => text::Encoder::new("UNKNOWN").collect(),
+        };
+        let rect = Rect::new(0, 0, client::EMU_WIDTH as i32, client::EMU_HEIGHT as i32);
+        let names = &self.names;
+        let viewer = PartyViewer::new(&owner, party, names, self.font, self.border_renderer, rect);
+
+        self.party_viewer = Some(viewer);
+        self.game_state = GameState::PartyView;
+        self.release_joypad();
+    }
+
+    /// Remove the prompt if it is answering a request from `player`
+    pub fn dismiss_prompt(&mut self, player: PlayerId) {
+        let interface_data = &mut self.interface_data.borrow_mut();
+        interface_data.withdraw_request(player);
+
+        let kind = match self.prompt {
+            Some((kind, _)) if kind.request_from() == Some(player) => kind,
+            _ => return,
+        };
+        self.prompt = None;
+        self.game_state = GameState::Emulator;
+
+        if let PromptKind::BattleChallenge(_) = kind {
+            interface_data.end_dialog(Some("The challenge was\nwithdrawn."));
+        }
+    }
+
+    /// The actions in the menu shown after talking to another player, leaving out the ones that
+    /// the server does not allow
+    fn interactions(&self) -> Vec<(Interaction, &'static str)> {
+        let battles = self.interface_data.borrow().allow_battles;
+        let trades = self.rules.allow_trades;
+        [
+            (Interaction::Battle, "BATTLE", battles),
+            (Interaction::Trade, "TRADE", trades),
+            (Interaction::ViewParty, "VIEW PARTY", true),
+            (Interaction::Chat, "CHAT", true),
+            (Interaction::Cancel, "CANCEL", true),
+        ]
+        .into_iter()
+        .filter(|&(_, _, allowed)| allowed)
+        .map(|(interaction, label, _)| (interaction, label))
+        .collect()
+    }
+
+    /// Ask the player what they want to do with the player that they just talked to. The game's
+    /// message box stays open with their greeting until the player has chosen.
+    fn show_interaction_menu(&mut self, id: PlayerId) {
+        let choices = self
+            .interactions()
+            .into_iter()
+            .map(|(_, label)| text::Encoder::new(label).collect())
+            .collect();
+        self.show_choices(PromptKind::Interaction(id), choices);
+    }
+
+    fn answer_prompt(&mut self, choice: usize) {
+        let kind = match self.prompt.take() {
+            Some((kind, _)) => kind,
+            None => return,
+        };
+        self.game_state = GameState::Emulator;
+
+        // The choices for pokemon are the party slots, followed by CANCEL
+        let party_size = self.emulator.mem.lb(offsets::get().party_count) as usize;
+
+        let request = match kind {
+            PromptKind::BattleChallenge(id) => {
+                self.interface_data.borrow_mut().end_dialog(None);
+                match choice {
+                    0 => NetworkRequest::AcceptBattle(id),
+                    _ => NetworkRequest::DeclineBattle(id),
+                }
+            }
+            PromptKind::TradeOffer(id) if choice < party_size => {
+                NetworkRequest::TradeOffer(id, choice as u8)
+            }
+            PromptKind::TradeConfirm(id) if choice == 0 => NetworkRequest::ConfirmTrade(id),
+            PromptKind::TradeOffer(id) | PromptKind::TradeConfirm(id) => {
+                NetworkRequest::CancelTrade(id)
+            }
+            PromptKind::Interaction(id) => {
+                let interaction = self.interactions().get(choice).map(|&(action, _)| action);
+                self.answer_interaction(id, interaction.unwrap_or(Interaction::Cancel))
+            }
+        };
+        self.interface_data.borrow_mut().network_request = request;
+    }
+
+    fn answer_interaction(&mut self, id: PlayerId, interaction: Interaction) -> NetworkRequest {
+        // A battle challenge keeps the message box open until the other player answers it
+        if interaction == Interaction::Battle {
+            return NetworkRequest::Battle(id);
+        }
+
+        self.interface_data.borrow_mut().end_dialog(None);
+        match interaction {
+            Interaction::Battle | Interaction::Cancel => NetworkRequest::None,
+            Interaction::Trade => NetworkRequest::Trade(id),
+            Interaction::ViewParty => NetworkRequest::ViewParty(id),
+            Interaction::Chat => NetworkRequest::Chat(id),
+        }
     }
 
     pub fn key_down(&mut self, keycode: KeyCode) {
         match self.game_state {
             GameState::Emulator => {
                 self.write_to_joypad(keycode, joypad::State::Pressed);
-                if keycode == KeyCode::Space {
+                if keycode == KeyCode::Space && self.rules.allow_fast_mode {
                     self.fast_mode = true;
                 }
             }
@@ -169,6 +436,23 @@ impl<'a> Game<'a> {
                 KeyCode::Down => self.menu.move_down(),
                 _ => {}
             },
+
+            GameState::RestoreMenu => match (keycode, self.restore_menu.as_mut()) {
+                (KeyCode::Up, Some((_, menu))) => menu.move_up(),
+                (KeyCode::Down, Some((_, menu))) => menu.move_down(),
+                _ => {}
+            },
+
+            GameState::Prompt => match (keycode, self.prompt.as_mut()) {
+                (KeyCode::Up, Some((_, prompt))) => prompt.move_up(),
+                (KeyCode::Down, Some((_, prompt))) => prompt.move_down(),
+                _ => {}
+            },
+            GameState::PartyView => match (keycode, self.party_viewer.as_mut()) {
+                (KeyCode::Up | KeyCode::Left, Some(viewer)) => viewer.prev_page(),
+                (KeyCode::Down | KeyCode::Right, Some(viewer)) => viewer.next_page(),
+                _ => {}
+            },
         }
     }
 
@@ -198,6 +482,7 @@ impl<'a> Game<'a> {
 
                     KeyCode::Escape => {
                         self.game_state = GameState::Emulator;
+                        self.chat_box.recipient = None;
                         // sdl_keyboard::stop_text_input();
                     }
 
@@ -209,17 +494,127 @@ impl<'a> Game<'a> {
                 KeyCode::Escape => {
                     self.game_state = GameState::Emulator;
                 }
+                KeyCode::Z | KeyCode::Enter if self.menu.selection() == RESTORE_SAVE_ITEM => {
+                    self.show_restore_menu();
+                }
+                _ => {}
+            },
+
+            GameState::RestoreMenu => match keycode {
+                KeyCode::Z | KeyCode::Enter => self.restore_selected_backup(),
+                KeyCode::X | KeyCode::Escape => {
+                    self.restore_menu = None;
+                    self.game_state = GameState::Menu;
+                }
+                _ => {}
+            },
+
+            GameState::Prompt => match keycode {
+                KeyCode::Z | KeyCode::Enter => {
+                    let choice = self.prompt.as_ref().map_or(0, |(_, prompt)| prompt.selection());
+                    self.answer_prompt(choice);
+                }
+                // Backing out of a prompt always chooses the last option
+                KeyCode::X | KeyCode::Escape => self.answer_prompt(usize::MAX),
+                _ => {}
+            },
+
+            GameState::PartyView => match keycode {
+                KeyCode::Z | KeyCode::X | KeyCode::Enter | KeyCode::Escape => {
+                    self.party_viewer = None;
+                    self.game_state = GameState::Emulator;
+                }
                 _ => {}
             },
         }
     }
 
+    /// Change the greeting shown to other players when they talk to us, and keep it for next time
+    pub fn set_greeting(&mut self, greeting: &str) {
+        let greeting: String = greeting.trim().chars().take(MAX_GREETING_LENGTH).collect();
+        if let Err(e) = fs::write(GREETING_PATH, &greeting) {
+            println!("Error writing {}: {}", GREETING_PATH, e);
+        }
+        self.player_data.greeting = greeting;
+    }
+
+    /// Show the list of backups of the save file that can be restored
+    fn show_restore_menu(&mut self) {
+        // Loading the cart again in the middle of something involving other players would leave
+        // them waiting for us
+        let interface_data = self.interface_data.borrow();
+        let busy = interface_data.state != InterfaceState::Normal
+            || interface_data.link_battle.is_some()
+            || interface_data.link_cable.is_some();
+        drop(interface_data);
+
+        let backups = save::list_backups(&self.cartridge.save_path);
+        let notice = if busy {
+            Some("You cannot restore a save now.")
+        }
+        else if backups.is_empty() {
+            Some("There are no backups to restore.")
+        }
+        else {
+            None
+        };
+        if let Some(notice) = notice {
+            let sender = interface::text::Encoder::new("SYSTEM").collect();
+            self.chat_box.add_message(sender, interface::text::Encoder::new(notice).collect());
+            return;
+        }
+
+        let items = backups.iter().map(|backup| backup.age()).collect();
+        let rect = Rect::new(
+            ((client::EMU_WIDTH - client::MENU_WIDTH) / 2) as i32,
+            0,
+            client::MENU_WIDTH as i32,
+            client::EMU_HEIGHT as i32,
+        );
+        let menu = ItemBox::new(items, self.font, self.border_renderer, rect);
+        self.restore_menu = Some((backups, menu));
+        self.game_state = GameState::RestoreMenu;
+    }
+
+    /// Restore the selected backup and load the cart again, so that the game starts from it
+    fn restore_selected_backup(&mut self) {
+        let (backups, menu) = match self.restore_menu.take() {
+            Some(restore_menu) => restore_menu,
+            None => return,
+        };
+        self.game_state = GameState::Emulator;
+
+        let backup = &backups[menu.selection()];
+        let message = match self.cartridge.restore(&mut self.emulator, backup) {
+            Ok(()) => format!("Restored the save from {}.", backup.age().to_lowercase()),
+            Err(e) => format!("Failed to restore the save: {}", e),
+        };
+        let sender = interface::text::Encoder::new("SYSTEM").collect();
+        self.chat_box.add_message(sender, interface::text::Encoder::new(&message).collect());
+    }
+
     pub fn text_input(&mut self, text: String) {
         if self.game_state == GameState::ChatBox {
             self.chat_box.message_buffer.push_str(&text);
         }
     }
 
+    fn release_joypad(&mut self) {
+        for keycode in [
+            KeyCode::Up,
+            KeyCode::Down,
+            KeyCode::Left,
+            KeyCode::Right,
+            KeyCode::Z,
+            KeyCode::X,
+            KeyCode::Enter,
+            KeyCode::RightShift,
+        ] {
+            self.write_to_joypad(keycode, joypad::State::Released);
+        }
+        self.fast_mode = false;
+    }
+
     fn write_to_joypad(&mut self, keycode: KeyCode, state: joypad::State) {
         let joypad = &mut self.emulator.mem.joypad;
         // TODO: Add custom key bindings
@@ -239,10 +634,14 @@ impl<'a> Game<'a> {
     }
 }
 
-fn draw_other_players(interface_data: &InterfaceData, self_data: &PlayerData, mem: &mut Memory) {
+fn draw_other_players(
+    interface_data: &InterfaceData,
+    self_data: &PlayerData,
+    map_header: &MapHeader,
+    mem: &mut Memory,
+) {
     for player in interface_data.players.values() {
-        if player.is_visible_to(self_data) {
-            let (x, y) = get_player_draw_position(self_data, player);
+        if let Some((x, y)) = get_player_draw_position(self_data, player, map_header) {
             let (index, flags) = get_sprite_index_and_flags(player);
             let sprite_data =
                 SpriteData { x: x as isize, y: y as isize, index: index as usize, flags };
@@ -252,20 +651,29 @@ fn draw_other_players(interface_data: &InterfaceData, self_data: &PlayerData, me
 }
 
 /// Get the screen coordinates of where to draw a target player adjusted relative to the local
-/// player's screen
-fn get_player_draw_position(self_player: &PlayerData, other_player: &PlayerData) -> (i32, i32) {
+/// player's screen. Returns `None` if the target player is not on the local player's map or on a
+/// map connected to it.
+fn get_player_draw_position(
+    self_player: &PlayerData,
+    other_player: &PlayerData,
+    map_header: &MapHeader,
+) -> Option<(i32, i32)> {
     let base_x = (graphics::WIDTH as i32) / 2 - 16;
     let base_y = (graphics::HEIGHT as i32) / 2 - 12;
 
-    let (self_x, self_y) = get_player_position(self_player);
-    let (other_x, other_y) = get_player_position(other_player);
+    let self_tile = map_header.translate(&self_player.movement_data)?;
+    let other_tile = map_header.translate(&other_player.movement_data)?;
 
-    (other_x - self_x + base_x, other_y - self_y + base_y)
+    let (self_x, self_y) = get_player_position(self_player, self_tile);
+    let (other_x, other_y) = get_player_position(other_player, other_tile);
+
+    Some((other_x - self_x + base_x, other_y - self_y + base_y))
 }
 
-fn get_player_position(player: &PlayerData) -> (i32, i32) {
-    let x = player.movement_data.map_x as i32 * 16;
-    let y = player.movement_data.map_y as i32 * 16;
+/// Get the position of a player in pixels, given the tile that they are on
+fn get_player_position(player: &PlayerData, (tile_x, tile_y): (i32, i32)) -> (i32, i32) {
+    let x = tile_x * 16;
+    let y = tile_y * 16;
 
     // Determine the offset of the player between tiles:
     // When a player begins walking, the walk counter is set to 8. For each step the walk counter
@@ -305,3 +713,7 @@ fn get_sprite_index_and_flags(player: &PlayerData) -> (isize, u8) {
 
     (index, flags)
 }
+
+pub fn yes_no() -> Vec<Vec<u8>> {
+    vec![text::Encoder::new("YES").collect(), text::Encoder::new("NO").collect()]
+}
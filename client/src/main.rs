@@ -1,10 +1,17 @@
-use std::{fs::File, io::prelude::*, net::TcpStream, path::Path};
+use std::{
+    fs::File,
+    io::prelude::*,
+    net::{IpAddr, SocketAddr},
+    path::{Path, PathBuf},
+};
 
 use gb_emu::emulator::Emulator;
+use interface::symbols::{self, Source};
+use network_common::DEFAULT_PORT;
 
 use crate::{
     net::{ClientManager, NetworkManager},
-    save::LocalSaveWrapper,
+    save::Cartridge,
 };
 
 mod border;
@@ -15,42 +22,94 @@ mod font;
 mod game;
 mod menu;
 mod net;
+mod party;
+mod prompt;
 mod save;
 
+/// The rom that is loaded if no path is given on the command line
+const DEFAULT_ROM: &str = "Pokemon Red.gb";
+
 #[macroquad::main("Pikemon")]
 async fn main() {
-    let socket = match std::env::args().nth(1) {
-        Some(ip_addr) => TcpStream::connect((&*ip_addr, 8080)).unwrap(),
-        // Assume localhost if there was no argument specified
-        None => TcpStream::connect(("localhost", 8080)).unwrap(),
+    // Assume localhost if there was no argument specified
+    let server_addr = match std::env::args().nth(1) {
+        Some(addr) => parse_server_addr(&addr),
+        None => ("localhost".to_string(), DEFAULT_PORT),
+    };
+    let rom_path = PathBuf::from(std::env::args().nth(2).unwrap_or_else(|| DEFAULT_ROM.into()));
+
+    let rom = {
+        let mut data = vec![];
+        let mut f = match File::open(&rom_path) {
+            Ok(f) => f,
+            Err(e) => panic!("Error opening '{}': {}", rom_path.display(), e),
+        };
+        f.read_to_end(&mut data).unwrap();
+        data
     };
 
+    // Every address used to modify the game depends on the version, so writing to a rom that we do
+    // not know about would corrupt its memory. Modified roms can be played by placing the symbol
+    // file from building them next to the rom.
+    let sym_path = rom_path.with_extension("sym");
+    let sym_path = if sym_path.exists() { Some(sym_path.as_path()) } else { None };
+    match symbols::select_for_rom(&rom, sym_path) {
+        Ok(Source::Version(version)) => println!("Loaded {}", version.name),
+        Ok(Source::Symbols { missing }) => {
+            println!("Loaded addresses from '{}'", sym_path.unwrap().display());
+            if !missing.is_empty() {
+                println!("Symbols not found, using the built-in addresses instead:");
+                println!("    {}", missing.join(", "));
+            }
+        }
+        Err(e) => {
+            println!("Unable to play '{}': {}", rom_path.display(), e);
+            return;
+        }
+    }
+
     let (local_update_sender, local_update_receiver) = crossbeam_channel::unbounded();
     let (global_update_sender, global_update_receiver) = crossbeam_channel::unbounded();
 
-    let network_manager = NetworkManager { socket, local_update_receiver, global_update_sender };
+    let network_manager = NetworkManager {
+        server_addr: server_addr.clone(),
+        local_update_receiver,
+        global_update_sender,
+    };
     let id = net::handle_network(network_manager).unwrap();
 
     let mut emulator = Box::new(Emulator::new());
 
-    let cart = {
-        let mut data = vec![];
-        let mut f = match File::open("Pokemon Red.gb") {
-            Ok(f) => f,
-            Err(e) => panic!("Error opening 'Pokemon Red.gb': {}", e),
-        };
-        f.read_to_end(&mut data).unwrap();
-        data
+    let cartridge = Cartridge {
+        rom,
+        save_path: rom_path.with_extension("sav"),
+        server_addr,
+        account: save::account_key(Path::new("account.key")),
     };
-    let save_path = Path::new("Pokemon Red.sav");
-
-    let save_file = Box::new(LocalSaveWrapper { path: save_path });
-    emulator.load_cart(&cart, Some(save_file));
-    emulator.start();
+    cartridge.load(&mut emulator);
 
     let client_manager = ClientManager::new(id, local_update_sender, global_update_receiver);
 
-    if let Err(e) = client::run(client_manager, emulator).await {
+    if let Err(e) = client::run(client_manager, emulator, cartridge).await {
         println!("Pikemon encountered an error and was forced to close. ({})", e);
     }
 }
+
+/// Parse a server address in the form `host` or `host:port`. IPv6 addresses are written either
+/// on their own or as `[addr]:port`.
+fn parse_server_addr(addr: &str) -> (String, u16) {
+    if let Ok(addr) = addr.parse::<SocketAddr>() {
+        return (addr.ip().to_string(), addr.port());
+    }
+    let unbracketed = addr.trim_start_matches('[').trim_end_matches(']');
+    if let Ok(ip) = unbracketed.parse::<IpAddr>() {
+        return (ip.to_string(), DEFAULT_PORT);
+    }
+
+    if let Some((host, port)) = addr.rsplit_once(':') {
+        if let Ok(port) = port.parse() {
+            return (host.to_string(), port);
+        }
+    }
+    (addr.to_string(), DEFAULT_PORT)
+}
@@ -7,7 +7,8 @@ use crate::{
 };
 
 pub struct ItemBox<'a> {
-    items: Vec<String>,
+    /// The text of each item, already encoded for the font
+    items: Vec<Vec<u8>>,
     selection: usize,
 
     font: &'a Font,
@@ -23,6 +24,17 @@ impl<'a> ItemBox<'a> {
         font: &'a Font,
         border: &'a BorderRenderer,
         rect: Rect,
+    ) -> ItemBox<'a> {
+        let items = items.iter().map(|item| text::Encoder::new(item).collect()).collect();
+        ItemBox::with_encoded_items(items, font, border, rect)
+    }
+
+    /// Create an item box from text that is already encoded, such as names read from the game
+    pub fn with_encoded_items(
+        items: Vec<Vec<u8>>,
+        font: &'a Font,
+        border: &'a BorderRenderer,
+        rect: Rect,
     ) -> ItemBox<'a> {
         let inner_rect = Rect::new(
             rect.x + 2 * font.char_width(),
@@ -40,7 +52,6 @@ impl<'a> ItemBox<'a> {
         let text_spacing = self.font.line_height();
         let mut y = self.inner_rect.y;
 
-        let mut text_buffer = vec![];
         for (i, item) in self.items.iter().enumerate() {
             if i == self.selection {
                 self.font.draw_char(
@@ -51,21 +62,23 @@ impl<'a> ItemBox<'a> {
                 );
             }
 
-            text_buffer.extend(text::Encoder::new(&item));
             y += draw_text(
                 renderer,
                 &self.font,
-                &text_buffer,
+                item,
                 &Rect::new(self.inner_rect.x, y, self.inner_rect.width, self.inner_rect.height),
             );
             y += text_spacing;
-            text_buffer.clear();
         }
 
         // Draw the chat border
         self.border.draw_box(renderer, self.outer_rect);
     }
 
+    pub fn selection(&self) -> usize {
+        self.selection
+    }
+
     pub fn move_down(&mut self) {
         self.selection += 1;
         if self.selection == self.items.len() {
@@ -1,79 +1,204 @@
 use std::{
-    io::{prelude::*, BufReader},
+    cmp,
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    io::BufReader,
     mem,
-    net::TcpStream,
+    net::{Shutdown, TcpStream},
     thread,
+    time::{Duration, Instant},
 };
 
 use crossbeam_channel::{Receiver, Sender};
+use gb_emu::mmu::Memory;
 use interface::{
     self,
-    data::{MovementData, PlayerData},
-    extract, text, InterfaceState, NetworkRequest,
+    data::{MovementData, Party, PlayerData, PokemonRecord},
+    extract, offsets, text, values, Dialog, InterfaceData, InterfaceState, NetworkRequest,
 };
 use network_common::{
+    codec,
     error::{NetworkError, NetworkResult},
-    NetworkEvent, PlayerId,
+    NetworkEvent, PlayerId, ResumeToken, HEARTBEAT_INTERVAL, IDLE_TIMEOUT, WRITE_TIMEOUT,
 };
 
-use crate::game::Game;
+use crate::{
+    chat::ChatBox,
+    game::{yes_no, Game, GameState, PromptKind},
+};
+
+/// The shortest and longest time to wait between attempts to reconnect to the server
+const MIN_RECONNECT_DELAY: Duration = Duration::from_secs(1);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(30);
+
+/// Requests from other players that need to be shown to the player
+enum PromptUpdate {
+    Challenge(PlayerId),
+    /// The state of our trade with a player has changed
+    Trade(PlayerId),
+    /// Remove anything being shown about a player
+    Withdrawn(PlayerId),
+    /// Show the party of a player that we asked to see
+    Party(PlayerId, Box<Party>),
+}
+
+/// A trade with another player that is in progress
+struct Trade {
+    partner: PlayerId,
+    /// The party slot that we offered and the pokemon that was in it at the time
+    ours: Option<(u8, PokemonRecord)>,
+    theirs: Option<PokemonRecord>,
+}
+
+impl Trade {
+    /// Whether the pokemon that we offered is still in the same party slot. The game keeps running
+    /// during a trade, so the party could have been changed since the offer was made.
+    fn offer_unchanged(&self, mem: &Memory) -> bool {
+        match &self.ours {
+            Some((slot, pokemon)) => extract::party_pokemon(mem, *slot) == *pokemon,
+            None => false,
+        }
+    }
+}
 
 pub struct NetworkManager {
-    pub socket: TcpStream,
+    pub server_addr: (String, u16),
     pub local_update_receiver: Receiver<NetworkEvent>,
     pub global_update_sender: Sender<NetworkEvent>,
 }
 
+/// Connect to the server and start the network threads. If the connection is later lost, the
+/// network threads keep trying to reconnect (resuming the same session), reporting the loss with
+/// `NetworkEvent::ServerFailure` and the new connection with `NetworkEvent::PlayerJoin`.
 pub fn handle_network(network_manager: NetworkManager) -> NetworkResult<PlayerId> {
-    let mut receiver_socket = BufReader::new(network_manager.socket.try_clone()?);
-    let mut data_buffer = String::new();
-
-    receiver_socket.read_line(&mut data_buffer)?;
-    let player_id = match serde_json::from_str(&data_buffer) {
-        Ok(NetworkEvent::PlayerJoin(id)) => id,
-        _ => return Err(NetworkError::DecodeError),
-    };
-    data_buffer.clear();
+    let (socket, player_id, token) = connect(&network_manager.server_addr, None)?;
 
-    let global_update_sender = network_manager.global_update_sender;
     thread::spawn(move || {
+        let NetworkManager { server_addr, local_update_receiver, global_update_sender } =
+            network_manager;
+
+        let (mut socket, mut token) = (socket, token);
         loop {
-            match receiver_socket.read_line(&mut data_buffer) {
-                Ok(_) => {
-                    let packet = serde_json::from_str(&data_buffer).unwrap();
-                    // TODO: better error handling
-                    let _ = global_update_sender.send(packet);
-                }
+            run_connection(socket, &local_update_receiver, &global_update_sender);
+            if global_update_sender.send(NetworkEvent::ServerFailure).is_err() {
+                // The client manager is gone, so there is no reason to reconnect
+                return;
+            }
 
-                Err(e) => {
-                    println!("Disconnected from server: {}", e);
-                    break;
+            let mut delay = MIN_RECONNECT_DELAY;
+            loop {
+                thread::sleep(delay);
+                match connect(&server_addr, Some(token)) {
+                    Ok((new_socket, id, new_token)) => {
+                        socket = new_socket;
+                        token = new_token;
+                        let _ = global_update_sender.send(NetworkEvent::PlayerJoin(id, token));
+                        break;
+                    }
+                    Err(e) => {
+                        println!("Failed to reconnect to server: {}", e);
+                        delay = cmp::min(delay * 2, MAX_RECONNECT_DELAY);
+                    }
                 }
             }
-            data_buffer.clear();
         }
     });
 
-    let local_update_receiver = network_manager.local_update_receiver;
-    let mut sender_socket = network_manager.socket;
-    thread::spawn(move || {
+    Ok(player_id)
+}
+
+/// Connect to the server and join the game, resuming a previous session if a token is provided.
+fn connect(
+    server_addr: &(String, u16),
+    token: Option<ResumeToken>,
+) -> NetworkResult<(TcpStream, PlayerId, ResumeToken)> {
+    let mut socket = TcpStream::connect((&*server_addr.0, server_addr.1))?;
+    socket.set_read_timeout(Some(IDLE_TIMEOUT))?;
+    socket.set_write_timeout(Some(WRITE_TIMEOUT))?;
+
+    codec::handshake(&mut socket)?;
+    codec::write_event(&mut socket, &NetworkEvent::JoinRequest(token))?;
+    match codec::read_event(&mut socket)? {
+        NetworkEvent::PlayerJoin(id, token) => Ok((socket, id, token)),
+        NetworkEvent::JoinRejected(reason) => Err(NetworkError::Rejected(reason)),
+        _ => Err(NetworkError::DecodeError),
+    }
+}
+
+/// Relay events between the server and the client manager until the connection is lost.
+fn run_connection(
+    socket: TcpStream,
+    local_update_receiver: &Receiver<NetworkEvent>,
+    global_update_sender: &Sender<NetworkEvent>,
+) {
+    let mut sender_socket = match socket.try_clone() {
+        Ok(socket) => socket,
+        Err(_) => return,
+    };
+
+    // Dropping this sender tells the sender thread that the connection is gone
+    let (closed_sender, closed_receiver) = crossbeam_channel::bounded::<()>(0);
+
+    let local_update_receiver = local_update_receiver.clone();
+    let sender_thread = thread::spawn(move || {
         loop {
-            let packet = serde_json::to_vec(&local_update_receiver.recv().unwrap()).unwrap();
+            let packet = crossbeam_channel::select! {
+                recv(local_update_receiver) -> packet => match packet {
+                    Ok(packet) => packet,
+                    Err(_) => break,
+                },
+                recv(closed_receiver) -> _ => break,
+                default(HEARTBEAT_INTERVAL) => NetworkEvent::Heartbeat,
+            };
 
-            // TODO: better error handling
-            let _ = sender_socket.write(&packet);
-            let _ = sender_socket.write(b"\n");
+            if let Err(e) = codec::write_event(&mut sender_socket, &packet) {
+                // Shutting down the socket causes the receiver to report the disconnect
+                println!("Failed to send update to server: {}", e);
+                let _ = sender_socket.shutdown(Shutdown::Both);
+                break;
+            }
         }
     });
 
-    Ok(player_id)
+    let mut receiver_socket = BufReader::new(socket);
+    loop {
+        match codec::read_event(&mut receiver_socket) {
+            Ok(NetworkEvent::Heartbeat) => {}
+
+            Ok(packet) => {
+                if global_update_sender.send(packet).is_err() {
+                    // The client manager is gone, so there is no one left to read updates
+                    break;
+                }
+            }
+
+            Err(e) => {
+                println!("Disconnected from server: {}", e);
+                break;
+            }
+        }
+    }
+
+    let _ = receiver_socket.get_ref().shutdown(Shutdown::Both);
+    drop(closed_sender);
+    let _ = sender_thread.join();
 }
 
 pub struct ClientManager {
     id: PlayerId,
+    connected: bool,
     last_state: Option<PlayerData>,
     full_update: Option<PlayerData>,
     movement_update: Option<MovementData>,
+    /// The map that we last told the server the connections of
+    linked_map: Option<u8>,
+    /// When we challenged the player that we are currently waiting for
+    challenge_sent: Option<Instant>,
+    /// The player whose challenge we accepted, who will start the battle
+    accepted_challenge: Option<PlayerId>,
+    trade: Option<Trade>,
+    /// The player whose party we asked to see
+    party_requested: Option<PlayerId>,
     update_sender: Sender<NetworkEvent>,
     update_receiver: Receiver<NetworkEvent>,
 }
@@ -86,9 +211,15 @@ impl ClientManager {
     ) -> ClientManager {
         ClientManager {
             id,
+            connected: true,
             last_state: None,
             full_update: None,
             movement_update: None,
+            linked_map: None,
+            challenge_sent: None,
+            accepted_challenge: None,
+            party_requested: None,
+            trade: None,
             update_sender,
             update_receiver,
         }
@@ -107,39 +238,263 @@ impl ClientManager {
     }
 
     pub fn send_update(&mut self, game: &mut Game) -> NetworkResult<()> {
+        // The server needs to know how maps are connected before it receives our position on a new
+        // map, so that it can work out who can see us.
+        if let Some(map) = game.map_header.as_ref() {
+            if self.linked_map != Some(map.map_id) {
+                self.linked_map = Some(map.map_id);
+                self.send(NetworkEvent::MapConnections(map.map_id, map.connected_maps()));
+            }
+        }
+
         if self.movement_update.is_some() {
             let update_data = mem::replace(&mut self.movement_update, None).unwrap();
-            self.update_sender
-                .send(NetworkEvent::MovementUpdate(self.id, update_data))
-                .map_err(|_| NetworkError::SendError)?;
+            self.send(NetworkEvent::MovementUpdate(self.id, update_data));
         }
 
         if self.full_update.is_some() {
             let update_data = mem::replace(&mut self.full_update, None).unwrap();
-            self.update_sender
-                .send(NetworkEvent::FullUpdate(self.id, update_data))
-                .map_err(|_| NetworkError::SendError)?;
+            self.send(NetworkEvent::FullUpdate(self.id, update_data));
         }
 
         if game.chat_box.message_ready {
             self.send_message(game)?;
         }
 
-        match game.interface_data.borrow().network_request {
+        let request = mem::replace(
+            &mut game.interface_data.borrow_mut().network_request,
+            NetworkRequest::None,
+        );
+        match request {
             NetworkRequest::None => {}
             NetworkRequest::Battle(id) => {
-                println!("Requesting battle");
-                self.update_sender
-                    .send(NetworkEvent::BattleDataRequest(id, self.id))
-                    .map_err(|_| NetworkError::SendError)?;
+                println!("Challenging player: {}", id);
+                self.send(NetworkEvent::BattleChallenge(id, self.id));
+                self.challenge_sent = Some(Instant::now());
+            }
+            NetworkRequest::Trade(id) => self.start_trade(game, id),
+            NetworkRequest::ViewParty(id) => {
+                self.send(NetworkEvent::PartyRequest(id, self.id));
+                self.party_requested = Some(id);
+            }
+            NetworkRequest::Chat(id) => {
+                // Only send the message to the player that we talked to
+                if let Some(player) = game.interface_data.borrow().players.get(&id) {
+                    game.chat_box.recipient = Some((id, player.name.clone()));
+                    game.game_state = GameState::ChatBox;
+                }
+            }
+            NetworkRequest::AcceptBattle(id) => {
+                println!("Accepting battle from player: {}", id);
+                self.send(NetworkEvent::BattleAccept(id, self.id));
+                let data = extract::battle_data(&game.emulator.mem);
+                self.send(NetworkEvent::BattleDataResponse(id, self.id, data));
+                self.accepted_challenge = Some(id);
+            }
+            NetworkRequest::DeclineBattle(id) => {
+                println!("Declining battle from player: {}", id);
+                self.send(NetworkEvent::BattleDecline(id, self.id));
+            }
+            NetworkRequest::BattleAction(id, action) => {
+                self.send(NetworkEvent::BattleAction(id, self.id, action));
+            }
+            NetworkRequest::TradeOffer(id, slot) => {
+                let trade = match self.trade.as_mut() {
+                    Some(trade) if trade.partner == id => trade,
+                    _ => return Ok(()),
+                };
+                let pokemon = extract::party_pokemon(&game.emulator.mem, slot);
+                trade.ours = Some((slot, pokemon.clone()));
+                self.send(NetworkEvent::TradeOffer(id, self.id, slot, pokemon));
+                self.update_trade_prompt(game, id);
+            }
+            NetworkRequest::ConfirmTrade(id) => {
+                let unchanged = match self.trade.as_ref() {
+                    Some(trade) if trade.partner == id => trade.offer_unchanged(&game.emulator.mem),
+                    _ => return Ok(()),
+                };
+                if unchanged {
+                    self.send(NetworkEvent::TradeConfirm(id, self.id));
+                }
+                else {
+                    self.cancel_changed_trade(&mut game.chat_box, id);
+                }
+            }
+            NetworkRequest::CancelTrade(id) => {
+                self.send(NetworkEvent::TradeCancel(id, self.id));
+                self.trade = None;
+            }
+            NetworkRequest::OpenLink => {
+                self.send(NetworkEvent::LinkRequest);
+            }
+            NetworkRequest::CloseLink => {
+                self.send(NetworkEvent::LinkClose(self.id));
+            }
+            NetworkRequest::LinkTransfer(id, data) => {
+                self.send(NetworkEvent::LinkTransfer(id, self.id, data));
             }
         }
 
-        game.interface_data.borrow_mut().network_request = NetworkRequest::None;
         Ok(())
     }
 
+    /// Start a trade with a player that we talked to
+    fn start_trade(&mut self, game: &mut Game, partner: PlayerId) {
+        if !game.rules.allow_trades {
+            add_notice(&mut game.chat_box, text::Encoder::new("Trading is disabled.").collect());
+        }
+        else if self.trade.is_some() || game.is_busy() {
+            add_notice(&mut game.chat_box, text::Encoder::new("You cannot trade now.").collect());
+        }
+        else if !game.interface_data.borrow().players.contains_key(&partner) {
+            let message = text::Encoder::new("Talk to a player before trading with them.");
+            add_notice(&mut game.chat_box, message.collect());
+        }
+        else {
+            self.trade = Some(Trade { partner, ours: None, theirs: None });
+            self.update_trade_prompt(game, partner);
+        }
+    }
+
+    /// Cancel a trade because the pokemon that we offered has been moved or changed
+    fn cancel_changed_trade(&mut self, chat_box: &mut ChatBox, partner: PlayerId) {
+        println!("Cancelling trade with player: {}, the offered pokemon changed", partner);
+        self.send(NetworkEvent::TradeCancel(partner, self.id));
+        self.trade = None;
+        let message = text::Encoder::new("The trade was cancelled as your party changed.");
+        add_notice(chat_box, message.collect());
+    }
+
+    /// Show the prompt for the next step of a trade
+    fn update_trade_prompt(&mut self, game: &mut Game, partner: PlayerId) {
+        let trade = match self.trade.as_ref() {
+            Some(trade) if trade.partner == partner => trade,
+            _ => return,
+        };
+        let partner_name = match game.interface_data.borrow().players.get(&partner) {
+            Some(player) => player.name.clone(),
+            None => text::Encoder::new("UNKNOWN").collect(),
+        };
+
+        let already_shown =
+            matches!(game.prompt, Some((PromptKind::TradeOffer(id), _)) if id == partner);
+
+        match (&trade.ours, &trade.theirs) {
+            (Some((_, ours)), Some(theirs)) => {
+                let mut message: Vec<u8> = text::Encoder::new("Trade ").collect();
+                message.extend(&ours.nickname);
+                message.extend(text::Encoder::new("\nfor "));
+                message.extend(&theirs.nickname);
+                message.extend(text::Encoder::new("?"));
+                game.show_prompt(PromptKind::TradeConfirm(partner), message, yes_no());
+            }
+
+            // Keep showing our own choice of pokemon if it is already being shown
+            (None, _) if already_shown => {}
+
+            (None, theirs) => {
+                if game.is_busy() {
+                    self.send(NetworkEvent::TradeCancel(partner, self.id));
+                    self.trade = None;
+                    return;
+                }
+
+                let mut message = partner_name;
+                match theirs {
+                    Some(theirs) => {
+                        message.extend(text::Encoder::new(" offers "));
+                        message.extend(&theirs.nickname);
+                        message.extend(text::Encoder::new(".\n"));
+                    }
+                    None => message.extend(text::Encoder::new(":\n")),
+                }
+                message.extend(text::Encoder::new("Choose a pokemon to\ntrade."));
+
+                let party = extract::player_party_names(&game.emulator.mem);
+                let choices = party.into_iter().chain([text::Encoder::new("CANCEL").collect()]);
+                game.show_prompt(PromptKind::TradeOffer(partner), message, choices.collect());
+            }
+
+            // We are waiting for the other player to choose their pokemon
+            (Some(_), None) => {}
+        }
+    }
+
+    /// Queue an event to be sent to the server. Updates are silently dropped while disconnected,
+    /// the receiver thread is responsible for reporting the disconnect.
+    fn send(&mut self, event: NetworkEvent) {
+        if self.connected && self.update_sender.send(event).is_err() {
+            self.connected = false;
+        }
+    }
+
     pub fn recv_update(&mut self, game: &mut Game) -> NetworkResult<()> {
+        let mut prompt_updates = vec![];
+        self.recv_events(game, &mut prompt_updates);
+        self.check_challenge_timeout(game);
+
+        // Prompts are handled after all other events, because showing a prompt needs access to the
+        // whole game.
+        for update in prompt_updates {
+            let id = match update {
+                PromptUpdate::Challenge(id) => id,
+                PromptUpdate::Trade(id) => {
+                    self.update_trade_prompt(game, id);
+                    continue;
+                }
+                PromptUpdate::Withdrawn(id) => {
+                    game.dismiss_prompt(id);
+                    continue;
+                }
+                PromptUpdate::Party(id, party) => {
+                    if !game.is_busy() {
+                        game.show_party(id, &party);
+                    }
+                    continue;
+                }
+            };
+
+            if game.is_busy() || game.prompt.is_some() {
+                self.send(NetworkEvent::BattleDecline(id, self.id));
+                continue;
+            }
+
+            // The challenge is shown in the game's message box as soon as the player is free, and
+            // the challenger gives up if that takes too long.
+            let interface_data = &mut game.interface_data.borrow_mut();
+            let name = match interface_data.players.get(&id) {
+                Some(player) => text::decode(&player.name),
+                None => "UNKNOWN".into(),
+            };
+            if !interface_data.queue_request(id, format!("{} wants to battle!", name)) {
+                self.send(NetworkEvent::BattleDecline(id, self.id));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Give up on a challenge if the other player takes too long to answer it, so that the game
+    /// does not stay frozen forever.
+    fn check_challenge_timeout(&mut self, game: &mut Game) {
+        let interface_data = &mut game.interface_data.borrow_mut();
+        let sent = match self.challenge_sent {
+            Some(sent) if interface_data.state == InterfaceState::Waiting => sent,
+            _ => {
+                self.challenge_sent = None;
+                return;
+            }
+        };
+
+        if sent.elapsed() >= Duration::from_secs(game.rules.request_timeout_secs) {
+            println!("Challenge to player: {} timed out", interface_data.last_interaction);
+            self.send(NetworkEvent::BattleCancel(interface_data.last_interaction, self.id));
+            self.challenge_sent = None;
+            end_challenge(interface_data, "Nobody answered.");
+        }
+    }
+
+    fn recv_events(&mut self, game: &mut Game, prompt_updates: &mut Vec<PromptUpdate>) {
         let interface_data = &mut game.interface_data.borrow_mut();
         loop {
             match self.update_receiver.try_recv() {
@@ -156,30 +511,219 @@ impl ClientManager {
                 Ok(NetworkEvent::PlayerQuit(id)) => {
                     println!("Player: {} quit.", id);
                     interface_data.players.remove(&id);
+
+                    // Neither side of a challenge can be answered once one of the players is gone
+                    prompt_updates.push(PromptUpdate::Withdrawn(id));
+                    interface_data.forfeit_link_battle(id);
+                    interface_data.link_closed(&mut game.emulator.mem, id);
+                    if self.accepted_challenge == Some(id) {
+                        self.accepted_challenge = None;
+                    }
+                    if self.trade.as_ref().is_some_and(|trade| trade.partner == id) {
+                        self.trade = None;
+                    }
+                    if is_waiting_for(interface_data, id) {
+                        end_challenge(interface_data, "Nobody answered.");
+                    }
+                }
+
+                Ok(NetworkEvent::BattleChallenge(_, id)) => {
+                    println!("Received battle challenge from player: {}", id);
+                    prompt_updates.push(PromptUpdate::Challenge(id));
+                }
+
+                Ok(NetworkEvent::BattleCancel(_, id)) => {
+                    println!("Player: {} withdrew their challenge", id);
+                    prompt_updates.push(PromptUpdate::Withdrawn(id));
+                }
+
+                Ok(NetworkEvent::TradeOffer(_, id, _, pokemon)) => {
+                    match self.trade.as_mut() {
+                        Some(trade) if trade.partner == id => trade.theirs = Some(pokemon),
+                        Some(_) => {
+                            // We can only trade with one player at a time
+                            self.send(NetworkEvent::TradeCancel(id, self.id));
+                            continue;
+                        }
+                        None => {
+                            let trade = Trade { partner: id, ours: None, theirs: Some(pokemon) };
+                            self.trade = Some(trade);
+                        }
+                    }
+                    prompt_updates.push(PromptUpdate::Trade(id));
+                }
+
+                Ok(NetworkEvent::TradeCancel(_, id)) => {
+                    if self.trade.as_ref().is_some_and(|trade| trade.partner == id) {
+                        self.trade = None;
+                        prompt_updates.push(PromptUpdate::Withdrawn(id));
+                        let message = text::Encoder::new("The trade was cancelled.");
+                        add_notice(&mut game.chat_box, message.collect());
+                    }
+                }
+
+                Ok(NetworkEvent::TradeComplete(id, slot, pokemon)) => {
+                    let unchanged = match self.trade.as_ref() {
+                        Some(trade) if trade.partner == id => {
+                            trade.offer_unchanged(&game.emulator.mem)
+                                && trade.ours.as_ref().map(|(ours, _)| *ours) == Some(slot)
+                        }
+                        _ => continue,
+                    };
+                    if !unchanged {
+                        // Never overwrite a pokemon that was not part of the trade
+                        self.cancel_changed_trade(&mut game.chat_box, id);
+                        continue;
+                    }
+                    self.trade = None;
+
+                    let species = game.names.species_name(pokemon.data.species);
+                    println!("Received {} in a trade with player: {}", species, id);
+                    let mut message: Vec<u8> = text::Encoder::new("Received ").collect();
+                    message.extend(&pokemon.nickname);
+                    message.extend(text::Encoder::new("!"));
+                    add_notice(&mut game.chat_box, message);
+                    interface::receive_trade(&mut game.emulator.mem, slot, pokemon);
+                }
+
+                Ok(NetworkEvent::PartyRequest(_, id)) => {
+                    let party = extract::player_party(&game.emulator.mem);
+                    self.send(NetworkEvent::PartyResponse(id, self.id, party));
+                }
+
+                Ok(NetworkEvent::PartyResponse(_, id, party)) => {
+                    // Only show parties that we asked for
+                    if self.party_requested == Some(id) {
+                        self.party_requested = None;
+                        prompt_updates.push(PromptUpdate::Party(id, Box::new(party)));
+                    }
+                }
+
+                Ok(NetworkEvent::PlayerUnavailable(id)) => {
+                    println!("Player: {} is not connected", id);
+                    if self.party_requested == Some(id) {
+                        self.party_requested = None;
+                    }
+                    if self.trade.as_ref().is_some_and(|trade| trade.partner == id) {
+                        self.trade = None;
+                        prompt_updates.push(PromptUpdate::Withdrawn(id));
+                    }
+                    if is_waiting_for(interface_data, id) {
+                        end_challenge(interface_data, "Nobody answered.");
+                    }
+                }
+
+                Ok(NetworkEvent::BattleAccept(_, id)) => {
+                    println!("Player: {} accepted our challenge", id);
+                }
+
+                Ok(NetworkEvent::BattleDecline(_, id)) => {
+                    if is_waiting_for(interface_data, id) {
+                        end_challenge(interface_data, "The challenge was\ndeclined.");
+                    }
+                }
+
+                Ok(NetworkEvent::BattleDataResponse(_, enemy_id, battle_data)) => {
+                    // Ignore responses to challenges that we are no longer waiting for, or that
+                    // come from a player that we did not challenge
+                    if !is_waiting_for(interface_data, enemy_id) {
+                        continue;
+                    }
+                    interface_data.end_dialog(None);
+
+                    // As the challenger we decide the random numbers used for the battle
+                    let random_numbers = random_numbers();
+                    let our_data = extract::battle_data(&game.emulator.mem);
+                    self.send(NetworkEvent::BattleStart(
+                        enemy_id,
+                        self.id,
+                        our_data,
+                        random_numbers.clone(),
+                    ));
+
+                    let mem = &mut game.emulator.mem;
+                    interface::set_battle(
+                        mem,
+                        interface_data,
+                        enemy_id,
+                        battle_data,
+                        &random_numbers,
+                        true,
+                    );
+                }
+
+                Ok(NetworkEvent::BattleStart(_, id, battle_data, random_numbers)) => {
+                    if self.accepted_challenge != Some(id) {
+                        continue;
+                    }
+                    self.accepted_challenge = None;
+
+                    // The challenger has already started the battle, so if we got into another
+                    // battle in the meantime the only option is to run from theirs.
+                    let mem = &mut game.emulator.mem;
+                    if mem.lb(offsets::get().active_battle) != values::ActiveBattle::None as u8 {
+                        let run = values::link_battle::RUN;
+                        self.send(NetworkEvent::BattleAction(id, self.id, run));
+                        continue;
+                    }
+                    interface::set_battle(
+                        mem,
+                        interface_data,
+                        id,
+                        battle_data,
+                        &random_numbers,
+                        false,
+                    );
+                }
+
+                Ok(NetworkEvent::BattleRejected(id, reason)) => {
+                    println!("Battle with player: {} was refused: {}", id, reason);
+                    if is_waiting_for(interface_data, id) {
+                        end_challenge(interface_data, "The battle was\nrefused.");
+                    }
+                    if self.accepted_challenge == Some(id) {
+                        self.accepted_challenge = None;
+                    }
+                    interface_data.forfeit_link_battle(id);
+                    add_notice(&mut game.chat_box, text::Encoder::new(&reason).collect());
+                }
+
+                Ok(NetworkEvent::BattleAction(_, id, action)) => {
+                    interface_data.receive_battle_action(id, action);
                 }
 
-                Ok(NetworkEvent::BattleDataRequest(_, id)) => {
-                    println!("Responding to battle request");
-                    let data = extract::battle_data(&game.emulator.mem);
-                    self.update_sender
-                        .send(NetworkEvent::BattleDataResponse(id, data))
-                        .map_err(|_| NetworkError::SendError)?;
+                Ok(NetworkEvent::LinkOpened(id)) => {
+                    println!("Link cable connected to player: {}", id);
+                    interface_data.link_opened(id);
                 }
 
-                Ok(NetworkEvent::BattleDataResponse(_, battle_data)) => {
-                    interface_data.state = InterfaceState::Normal;
-                    let enemy_id = interface_data.last_interaction;
-                    if let Some(enemy) = interface_data.players.get(&enemy_id) {
-                        interface::set_battle(&mut game.emulator.mem, enemy, battle_data);
+                Ok(NetworkEvent::LinkTransfer(_, id, data)) => {
+                    let reply = interface_data.link_transfer(&mut game.emulator.mem, id, data);
+                    self.send(NetworkEvent::LinkReply(id, self.id, reply));
+                }
+
+                Ok(NetworkEvent::LinkReply(_, id, data)) => {
+                    interface_data.link_reply(&mut game.emulator.mem, id, data);
+                }
+
+                Ok(NetworkEvent::LinkClose(id)) => {
+                    println!("Link cable to player: {} unplugged", id);
+                    interface_data.link_closed(&mut game.emulator.mem, id);
+                }
+
+                Ok(NetworkEvent::Rules(rules)) => {
+                    if !rules.allow_fast_mode {
+                        game.fast_mode = false;
                     }
+                    interface_data.allow_battles = rules.allow_battles;
+                    game.rules = rules;
                 }
 
-                Ok(NetworkEvent::UpdateRequest) => {
-                    println!("Responding to update request");
-                    let update_data = game.player_data.clone();
-                    self.update_sender
-                        .send(NetworkEvent::FullUpdate(self.id, update_data))
-                        .map_err(|_| NetworkError::SendError)?;
+                Ok(NetworkEvent::ServerMessage(msg)) => {
+                    game.chat_box.add_message(
+                        text::Encoder::new("SERVER").collect(),
+                        text::Encoder::new(&msg).collect(),
+                    );
                 }
 
                 Ok(NetworkEvent::Chat(id, msg)) => {
@@ -190,23 +734,124 @@ impl ClientManager {
                     game.chat_box.add_message(player_name, text::Encoder::new(&*msg).collect());
                 }
 
-                Ok(_) => unimplemented!(),
+                Ok(NetworkEvent::DirectChat(_, id, msg)) => {
+                    let mut label: Vec<u8> = text::Encoder::new("FROM ").collect();
+                    match interface_data.players.get(&id) {
+                        Some(player) => label.extend(&player.name),
+                        None => label.extend(text::Encoder::new("UNKNOWN")),
+                    }
+                    game.chat_box.add_message(label, text::Encoder::new(&msg).collect());
+                }
+
+                Ok(NetworkEvent::ServerFailure) => {
+                    self.connected = false;
+                    interface_data.players.clear();
+
+                    // Nothing is going to answer any outstanding requests, so make sure that the
+                    // game does not stay frozen waiting for them.
+                    if let Some(Dialog::Request(id)) = interface_data.dialog() {
+                        prompt_updates.push(PromptUpdate::Withdrawn(id));
+                    }
+                    interface_data.end_dialog(None);
+                    interface_data.clear_request();
+                    if let Some(battle) = interface_data.link_battle.as_ref() {
+                        let opponent = battle.opponent;
+                        interface_data.forfeit_link_battle(opponent);
+                    }
+                    let link_partner = interface_data.link_cable.as_ref().and_then(|c| c.partner);
+                    if let Some(partner) = link_partner {
+                        interface_data.link_closed(&mut game.emulator.mem, partner);
+                    }
+                    self.accepted_challenge = None;
+                    if let Some(trade) = self.trade.take() {
+                        prompt_updates.push(PromptUpdate::Withdrawn(trade.partner));
+                    }
+                    game.chat_box.add_message(
+                        text::Encoder::new("SERVER").collect(),
+                        text::Encoder::new("Connection lost. Reconnecting...").collect(),
+                    );
+                }
+
+                // We have reconnected to the server after losing the connection
+                Ok(NetworkEvent::PlayerJoin(id, _)) => {
+                    self.id = id;
+                    self.connected = true;
+
+                    // Clear the last state so that a full update is sent on the next frame. The
+                    // server responds to this by sending us the state of everyone we can see.
+                    self.last_state = None;
+                    self.linked_map = None;
+                    game.chat_box.add_message(
+                        text::Encoder::new("SERVER").collect(),
+                        text::Encoder::new("Reconnected.").collect(),
+                    );
+                }
+
+                // Ignore events that we do not understand rather than failing
+                Ok(_) => {}
                 _ => break,
             }
         }
-
-        Ok(())
     }
 
     pub fn send_message(&mut self, game: &mut Game) -> NetworkResult<()> {
         let msg = game.chat_box.get_message_buffer();
+        let recipient = game.chat_box.recipient.take();
+
+        // Commands are handled locally instead of being sent as chat messages
+        if msg.trim() == "/trade" {
+            let partner = game.interface_data.borrow().last_interaction;
+            self.start_trade(game, partner);
+            return Ok(());
+        }
+        let command = msg.trim();
+        if command == "/greeting" || command.starts_with("/greeting ") {
+            game.set_greeting(&command["/greeting".len()..]);
+            let message = format!("Greeting set to: {}", game.player_data.greeting_message());
+            add_notice(&mut game.chat_box, text::Encoder::new(&message).collect());
+            return Ok(());
+        }
+
+        if let Some((id, name)) = recipient {
+            let mut label: Vec<u8> = text::Encoder::new("TO ").collect();
+            label.extend(name);
+            game.chat_box.add_message(label, text::Encoder::new(&msg).collect());
+            self.send(NetworkEvent::DirectChat(id, self.id, msg));
+            return Ok(());
+        }
+
         let user_name = game.player_data.name.clone();
 
         game.chat_box.add_message(user_name, text::Encoder::new(&msg).collect());
-        self.update_sender
-            .send(NetworkEvent::Chat(self.id, msg))
-            .map_err(|_| NetworkError::SendError)?;
+        self.send(NetworkEvent::Chat(self.id, msg));
 
         Ok(())
     }
 }
+
+/// Show a message in the chat box that only we can see
+fn add_notice(chat_box: &mut ChatBox, message: Vec<u8>) {
+    chat_box.add_message(text::Encoder::new("SYSTEM").collect(), message);
+}
+
+/// Generate the random numbers that are shared by both players in a battle
+fn random_numbers() -> Vec<u8> {
+    let state = RandomState::new();
+    (0..values::link_battle::NUM_RANDOM_NUMBERS)
+        .map(|i| {
+            let mut hasher = state.build_hasher();
+            hasher.write_usize(i);
+            hasher.finish() as u8
+        })
+        .collect()
+}
+
+/// Whether the game's message box is open waiting for `id` to answer us
+fn is_waiting_for(interface_data: &InterfaceData, id: PlayerId) -> bool {
+    interface_data.dialog() == Some(Dialog::Interaction(id))
+}
+
+/// Finish the message box left open by a challenge and let the game continue
+fn end_challenge(interface_data: &mut InterfaceData, message: &str) {
+    interface_data.end_dialog(Some(message));
+}
@@ -0,0 +1,154 @@
+//! A window showing a summary of another player's party
+use std::iter;
+
+use interface::{
+    data::{NameTables, Party, PokemonData},
+    text,
+    values::{moves, status},
+};
+
+use crate::{
+    border::BorderRenderer,
+    common::{Rect, Renderer},
+    font::{draw_text, Font},
+};
+
+pub struct PartyViewer<'a> {
+    title: Vec<u8>,
+    /// The text of each line of the summary, already encoded for the font and split into pages
+    /// that fit in the window
+    pages: Vec<Vec<Vec<u8>>>,
+    page: usize,
+
+    font: &'a Font,
+    border: &'a BorderRenderer,
+
+    outer_rect: Rect,
+    inner_rect: Rect,
+}
+
+impl<'a> PartyViewer<'a> {
+    /// Create a summary of `party`, using `names` for the names of species and moves
+    pub fn new(
+        owner: &[u8],
+        party: &Party,
+        names: &NameTables,
+        font: &'a Font,
+        border: &'a BorderRenderer,
+        rect: Rect,
+    ) -> PartyViewer<'a> {
+        let mut title = owner.to_vec();
+        title.extend(text::Encoder::new("'s party"));
+
+        let inner_rect = Rect::new(
+            rect.x + 2 * font.char_width(),
+            rect.y + 2 * font.line_height(),
+            rect.width - 4 * font.char_width(),
+            rect.height - 4 * font.line_height(),
+        );
+
+        // Each page starts with the title and a blank line, and ends with the page number
+        let window_lines = (inner_rect.height / font.line_height()).max(0) as usize;
+        let page_lines = window_lines.saturating_sub(3);
+
+        // Pokemon are not split across pages, unless a single pokemon does not fit on a page
+        let mut pages: Vec<Vec<Vec<u8>>> = vec![vec![]];
+        for pokemon in party.members() {
+            let lines = summary(pokemon, names);
+            let page = pages.last_mut().unwrap();
+            if !page.is_empty() && page.len() + lines.len() > page_lines {
+                pages.push(vec![]);
+            }
+            let page = pages.last_mut().unwrap();
+            page.extend(lines);
+            page.push(vec![]);
+        }
+        if party.num_pokemon == 0 {
+            pages[0].push(text::Encoder::new("No pokemon.").collect());
+        }
+
+        PartyViewer { title, pages, page: 0, font, border, outer_rect: rect, inner_rect }
+    }
+
+    pub fn next_page(&mut self) {
+        self.page = (self.page + 1) % self.pages.len();
+    }
+
+    pub fn prev_page(&mut self) {
+        self.page = (self.page + self.pages.len() - 1) % self.pages.len();
+    }
+
+    pub fn draw(&self, renderer: &mut Renderer) {
+        let line_height = self.font.line_height();
+        let bottom = self.inner_rect.y + self.inner_rect.height;
+
+        let blank = vec![];
+        let mut y = self.inner_rect.y;
+        for line in [&self.title, &blank].into_iter().chain(&self.pages[self.page]) {
+            // Anything that does not fit in the window is cut off
+            if y + line_height > bottom {
+                break;
+            }
+            let target = Rect::new(self.inner_rect.x, y, self.inner_rect.width, line_height);
+            draw_text(renderer, self.font, line, &target);
+            y += line_height;
+        }
+
+        if self.pages.len() > 1 {
+            let page_number = format!("PAGE {}/{}", self.page + 1, self.pages.len());
+            let page_number: Vec<u8> = text::Encoder::new(&page_number).collect();
+            let y = bottom - line_height;
+            let target = Rect::new(self.inner_rect.x, y, self.inner_rect.width, line_height);
+            draw_text(renderer, self.font, &page_number, &target);
+        }
+
+        self.border.draw_box(renderer, self.outer_rect);
+    }
+}
+
+/// The lines describing a single pokemon: its species, level, HP and status, followed by its moves
+fn summary(pokemon: &PokemonData, names: &NameTables) -> Vec<Vec<u8>> {
+    let header = format!(
+        "{} L{} HP {}/{} {}",
+        names.species_name(pokemon.species),
+        pokemon.level,
+        pokemon.hp,
+        pokemon.max_hp,
+        status_name(pokemon.status)
+    );
+
+    let (a, b, c, d) = pokemon.moves;
+    let move_names = [a, b, c, d]
+        .into_iter()
+        .filter(|&move_id| move_id != moves::NONE)
+        .map(|move_id| format!("  {}", names.move_name(move_id)));
+
+    iter::once(header).chain(move_names).map(|line| text::Encoder::new(&line).collect()).collect()
+}
+
+/// The abbreviation that the game uses for a status condition. The lowest bits of the status are
+/// the number of turns left asleep, and each other condition has its own bit.
+fn status_name(value: u8) -> &'static str {
+    let has = |condition: u8| value & (1 << condition) != 0;
+    if value & 0x07 != 0 {
+        "SLP"
+    }
+    else if has(status::POISON) {
+        "PSN"
+    }
+    else if has(status::BURN) {
+        "BRN"
+    }
+    else if has(status::FREEZE) {
+        "FRZ"
+    }
+    else if has(status::PARALYZE) {
+        "PAR"
+    }
+    else if value == status::NONE {
+        "OK"
+    }
+    else {
+        "?"
+    }
+}
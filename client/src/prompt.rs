@@ -0,0 +1,79 @@
+//! A message box that asks the player to choose between a set of options
+use crate::{
+    border::BorderRenderer,
+    common::{Rect, Renderer},
+    font::{draw_text, Font},
+    menu::ItemBox,
+};
+
+pub struct PromptBox<'a> {
+    /// The question, or `None` if it is already being shown in the game's own message box
+    message: Option<Vec<u8>>,
+    choices: ItemBox<'a>,
+
+    font: &'a Font,
+    border: &'a BorderRenderer,
+
+    outer_rect: Rect,
+    inner_rect: Rect,
+}
+
+impl<'a> PromptBox<'a> {
+    /// Create a new prompt box. The message is drawn in a box filling `rect` and the choices are
+    /// drawn in a smaller box above its right hand corner, similar to the game's own YES/NO menu.
+    /// Both the message and the choices must already be encoded for the font.
+    pub fn new(
+        message: Option<Vec<u8>>,
+        choices: Vec<Vec<u8>>,
+        font: &'a Font,
+        border: &'a BorderRenderer,
+        rect: Rect,
+    ) -> PromptBox<'a> {
+        let inner_rect = Rect::new(
+            rect.x + font.char_width(),
+            rect.y + font.line_height(),
+            rect.width - 2 * font.char_width(),
+            rect.height - 2 * font.line_height(),
+        );
+
+        let longest_choice = choices.iter().map(|choice| choice.len()).max().unwrap_or(0) as i32;
+        let choices_width = (longest_choice + 4) * font.char_width();
+        let choices_height = (2 * choices.len() as i32 + 2) * font.line_height();
+        let choices_rect = Rect::new(
+            rect.x + rect.width - choices_width,
+            rect.y - choices_height,
+            choices_width,
+            choices_height,
+        );
+
+        PromptBox {
+            message,
+            choices: ItemBox::with_encoded_items(choices, font, border, choices_rect),
+            font,
+            border,
+            outer_rect: rect,
+            inner_rect,
+        }
+    }
+
+    pub fn move_up(&mut self) {
+        self.choices.move_up();
+    }
+
+    pub fn move_down(&mut self) {
+        self.choices.move_down();
+    }
+
+    /// The index of the currently selected choice
+    pub fn selection(&self) -> usize {
+        self.choices.selection()
+    }
+
+    pub fn draw(&self, renderer: &mut Renderer) {
+        if let Some(message) = &self.message {
+            draw_text(renderer, self.font, message, &self.inner_rect);
+            self.border.draw_box(renderer, self.outer_rect);
+        }
+        self.choices.draw(renderer);
+    }
+}
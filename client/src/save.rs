@@ -1,10 +1,135 @@
 use std::{
+    cmp::Reverse,
+    collections::hash_map::RandomState,
     fs::{self, File},
+    hash::{BuildHasher, Hasher},
     io::{self, prelude::*},
-    path::Path,
+    net::TcpStream,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
-use gb_emu::cart::SaveFile;
+use crossbeam_channel::{Receiver, Sender};
+use gb_emu::{cart::SaveFile, emulator::Emulator};
+use network_common::{codec, error::NetworkResult, AccountKey, NetworkEvent, RemoteSave};
+
+/// How many old versions of the save file are kept in the backup directory
+const MAX_BACKUPS: usize = 10;
+
+/// Everything needed to load the cart, kept so that the cart can be loaded again after restoring a
+/// backup
+pub struct Cartridge {
+    pub rom: Vec<u8>,
+    pub save_path: PathBuf,
+    pub server_addr: (String, u16),
+    pub account: AccountKey,
+}
+
+impl Cartridge {
+    /// Load the cart into the emulator and start it from the beginning
+    pub fn load(&self, emulator: &mut Emulator) {
+        let save_file =
+            RemoteSaveWrapper::new(self.save_path.clone(), self.server_addr.clone(), self.account);
+        emulator.load_cart(&self.rom, Some(Box::new(save_file)));
+        emulator.start();
+    }
+
+    /// Replace the save file with a backup and load the cart again, so that the game starts from
+    /// it. The replaced save file is backed up as well, so restoring a backup can be undone.
+    pub fn restore(&self, emulator: &mut Emulator, backup: &Backup) -> io::Result<()> {
+        let data = fs::read(&backup.path)?;
+
+        // Unload the running game first, so that it cannot write its own save over the backup
+        emulator.load_cart(&self.rom, None);
+        LocalSaveWrapper { path: &self.save_path }.save(&data);
+
+        // Treat the restored save like a save made while offline, so that it is uploaded to the
+        // server instead of being replaced by the save already there.
+        let sync_path = sync_path(&self.save_path);
+        let mut state = SyncState::load(&sync_path);
+        state.counter += 1;
+        state.save(&sync_path);
+
+        self.load(emulator);
+        Ok(())
+    }
+}
+
+/// An old version of the save file
+pub struct Backup {
+    pub path: PathBuf,
+    /// When the save was replaced, in seconds since the unix epoch
+    pub time: u64,
+    /// The milliseconds part of the time, so that backups made within a second stay in order
+    millis: u32,
+}
+
+impl Backup {
+    /// A short description of how long ago the backup was made, e.g. `5 MIN AGO`
+    pub fn age(&self) -> String {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |t| t.as_secs());
+        let age = now.saturating_sub(self.time);
+        match age {
+            0..=59 => format!("{} SEC AGO", age),
+            60..=3599 => format!("{} MIN AGO", age / 60),
+            3600..=86399 => format!("{} HOURS AGO", age / 3600),
+            _ => format!("{} DAYS AGO", age / 86400),
+        }
+    }
+}
+
+/// The directory that backups of a save file are kept in
+fn backup_dir(save_path: &Path) -> PathBuf {
+    save_path.with_file_name("backups")
+}
+
+/// List the backups of a save file, newest first
+pub fn list_backups(save_path: &Path) -> Vec<Backup> {
+    let prefix = backup_prefix(save_path);
+    let entries = match fs::read_dir(backup_dir(save_path)) {
+        Ok(entries) => entries,
+        Err(_) => return vec![],
+    };
+
+    let mut backups: Vec<_> = entries
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+            let name = path.file_stem()?.to_str()?;
+            // Older backups were named without the milliseconds
+            let time = name.strip_prefix(&prefix)?;
+            let (time, millis) = time.split_once('.').unwrap_or((time, "0"));
+            let (time, millis) = (time.parse().ok()?, millis.parse().ok()?);
+            Some(Backup { path, time, millis })
+        })
+        .collect();
+    backups.sort_by_key(|backup| Reverse((backup.time, backup.millis)));
+    backups
+}
+
+/// Copy the current save file into the backup directory, and remove the oldest backups
+fn backup(save_path: &Path) -> io::Result<()> {
+    if !save_path.exists() {
+        return Ok(());
+    }
+
+    let time = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    fs::create_dir_all(backup_dir(save_path))?;
+    let prefix = backup_prefix(save_path);
+    let name = format!("{}{}.{:03}.sav", prefix, time.as_secs(), time.subsec_millis());
+    fs::copy(save_path, backup_dir(save_path).join(name))?;
+
+    for old in list_backups(save_path).iter().skip(MAX_BACKUPS) {
+        fs::remove_file(&old.path)?;
+    }
+    Ok(())
+}
+
+fn backup_prefix(save_path: &Path) -> String {
+    let stem = save_path.file_stem().map_or("save".into(), |stem| stem.to_string_lossy());
+    format!("{}-", stem)
+}
 
 pub struct LocalSaveWrapper<'a> {
     pub path: &'a Path,
@@ -26,6 +151,11 @@ impl<'a> SaveFile for LocalSaveWrapper<'a> {
             return;
         }
 
+        // Keep the old file around in case the new save turns out to be bad
+        if let Err(e) = backup(self.path) {
+            println!("Error backing up the old save file: {}", e);
+        }
+
         // At this stage the new save file has been successfully written, so we can safely remove
         // the old file if it exists.
         match fs::remove_file(&self.path) {
@@ -48,3 +178,173 @@ impl<'a> SaveFile for LocalSaveWrapper<'a> {
         eprintln!("Saved state: {}", self.path.display());
     }
 }
+
+/// How long to wait for the server when loading or uploading a save
+const REMOTE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Tracks how the cached save relates to the save stored on the server: `counter` is increased on
+/// every save, and `synced` is the counter of the last save that the server accepted.
+#[derive(Clone, Copy, Default)]
+struct SyncState {
+    counter: u64,
+    synced: u64,
+}
+
+impl SyncState {
+    fn load(path: &Path) -> SyncState {
+        let text = fs::read_to_string(path).unwrap_or_default();
+        let mut values = text.split_whitespace().map(|value| value.parse().unwrap_or(0));
+        SyncState { counter: values.next().unwrap_or(0), synced: values.next().unwrap_or(0) }
+    }
+
+    fn save(&self, path: &Path) {
+        if let Err(e) = fs::write(path, format!("{} {}", self.counter, self.synced)) {
+            println!("Error writing {}: {}", path.display(), e);
+        }
+    }
+}
+
+/// Stores the save file on the server, keeping a local copy so that the game can still be played
+/// when the server cannot be reached.
+pub struct RemoteSaveWrapper {
+    cache_path: PathBuf,
+    server_addr: (String, u16),
+    account: AccountKey,
+    state: Arc<Mutex<SyncState>>,
+    uploads: Sender<(u64, Vec<u8>)>,
+}
+
+impl RemoteSaveWrapper {
+    pub fn new(cache_path: PathBuf, server_addr: (String, u16), account: AccountKey) -> Self {
+        let state = Arc::new(Mutex::new(SyncState::load(&sync_path(&cache_path))));
+        let (uploads, upload_receiver) = crossbeam_channel::unbounded();
+
+        // Uploads happen on their own thread so that the game does not freeze while saving
+        let uploader = Uploader {
+            sync_path: sync_path(&cache_path),
+            server_addr: server_addr.clone(),
+            account,
+            state: state.clone(),
+        };
+        thread::spawn(move || uploader.run(upload_receiver));
+
+        RemoteSaveWrapper { cache_path, server_addr, account, state, uploads }
+    }
+}
+
+impl SaveFile for RemoteSaveWrapper {
+    fn load(&mut self, data: &mut [u8]) {
+        LocalSaveWrapper { path: &self.cache_path }.load(data);
+
+        let remote = match request(&self.server_addr, NetworkEvent::SaveDownload(self.account)) {
+            Ok(NetworkEvent::SaveData(remote)) => remote,
+            Ok(_) => return println!("Unexpected reply when loading save from server"),
+            Err(e) => return println!("Could not load save from server, playing offline: {}", e),
+        };
+
+        let mut state = self.state.lock().unwrap();
+        let remote_counter = remote.as_ref().map_or(0, |save| save.counter);
+        if remote_counter == state.synced {
+            // The server has the last save that we uploaded, so make sure that it also gets any
+            // saves that were made while offline.
+            if state.counter > state.synced {
+                let _ = self.uploads.send((state.counter, data.to_vec()));
+            }
+        }
+        else if state.counter == state.synced {
+            // The game was saved somewhere else since we last played, so continue from there
+            if let Some(remote) = remote {
+                let len = data.len().min(remote.data.len());
+                data[..len].copy_from_slice(&remote.data[..len]);
+                LocalSaveWrapper { path: &self.cache_path }.save(data);
+                *state = SyncState { counter: remote.counter, synced: remote.counter };
+                state.save(&sync_path(&self.cache_path));
+                println!("Loaded save from server");
+            }
+        }
+        else {
+            println!(
+                "The save on the server has changed since this save was made, keeping the local \
+                save. Delete {} to use the save on the server instead.",
+                sync_path(&self.cache_path).display()
+            );
+        }
+    }
+
+    fn save(&mut self, data: &[u8]) {
+        LocalSaveWrapper { path: &self.cache_path }.save(data);
+
+        let mut state = self.state.lock().unwrap();
+        state.counter += 1;
+        state.save(&sync_path(&self.cache_path));
+        let _ = self.uploads.send((state.counter, data.to_vec()));
+    }
+}
+
+struct Uploader {
+    sync_path: PathBuf,
+    server_addr: (String, u16),
+    account: AccountKey,
+    state: Arc<Mutex<SyncState>>,
+}
+
+impl Uploader {
+    fn run(self, uploads: Receiver<(u64, Vec<u8>)>) {
+        while let Ok(mut upload) = uploads.recv() {
+            // Only the newest save matters if the uploads have fallen behind
+            while let Ok(newer) = uploads.try_recv() {
+                upload = newer;
+            }
+
+            let (counter, data) = upload;
+            let synced = self.state.lock().unwrap().synced;
+            let save = RemoteSave { counter, data };
+            match request(&self.server_addr, NetworkEvent::SaveUpload(self.account, synced, save)) {
+                Ok(NetworkEvent::SaveAccepted(counter)) => {
+                    let mut state = self.state.lock().unwrap();
+                    state.synced = state.synced.max(counter);
+                    state.save(&self.sync_path);
+                    eprintln!("Uploaded save to server");
+                }
+                Ok(NetworkEvent::SaveConflict(_)) => {
+                    println!("The save on the server has changed, the save was only kept locally");
+                }
+                Ok(_) => println!("Unexpected reply when uploading save to server"),
+                Err(e) => println!("Could not upload save to server, it was kept locally: {}", e),
+            }
+        }
+    }
+}
+
+/// Load the account key from `path`, creating a new key if there is not one yet
+pub fn account_key(path: &Path) -> AccountKey {
+    let key = fs::read_to_string(path).ok();
+    let key = key.and_then(|text| u64::from_str_radix(text.trim(), 16).ok());
+    if let Some(key) = key {
+        return key;
+    }
+
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u128(SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |t| t.as_nanos()));
+    let key = hasher.finish();
+    if let Err(e) = fs::write(path, format!("{:016x}", key)) {
+        println!("Error writing {}: {}", path.display(), e);
+    }
+    key
+}
+
+/// The file that the sync state of a save is kept in
+fn sync_path(cache_path: &Path) -> PathBuf {
+    cache_path.with_extension("sav.sync")
+}
+
+/// Open a connection to the server to send a single save request, returning the reply
+fn request(server_addr: &(String, u16), event: NetworkEvent) -> NetworkResult<NetworkEvent> {
+    let mut socket = TcpStream::connect((&*server_addr.0, server_addr.1))?;
+    socket.set_read_timeout(Some(REMOTE_TIMEOUT))?;
+    socket.set_write_timeout(Some(REMOTE_TIMEOUT))?;
+
+    codec::handshake(&mut socket)?;
+    codec::write_event(&mut socket, &event)?;
+    codec::read_event(&mut socket)
+}
@@ -1,10 +1,22 @@
 use gb_emu::mmu::Memory;
 
 use crate::{
-    extract,
+    extract, text,
     values::{moves, pokeid, status, types, Direction},
 };
 
+/// Replaced with the player's name when a greeting is shown
+pub const GREETING_NAME: &str = "<NAME>";
+
+/// Used in place of the name of a player whose details have not been received
+pub const UNKNOWN_PLAYER_NAME: &str = "PLAYER";
+
+/// Shown when talking to a player who has not set a greeting
+pub const DEFAULT_GREETING: &str = "<NAME> has nothing to say.";
+
+/// The longest greeting that is shown, longer greetings are cut off
+pub const MAX_GREETING_LENGTH: usize = 100;
+
 #[derive(Copy, Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
 pub struct MovementData {
     pub map_id: u8,
@@ -35,6 +47,64 @@ impl MovementData {
     }
 }
 
+/// A connection from one map to an adjacent map, as stored in the map header. The alignment values
+/// are what the game adds to the player's coordinates when they walk across the connection.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct MapConnection {
+    pub map_id: u8,
+    pub y_align: u8,
+    pub x_align: u8,
+}
+
+/// The parts of a map header that we care about
+#[derive(Clone, Debug, PartialEq)]
+pub struct MapHeader {
+    pub map_id: u8,
+    /// The height of the map in blocks (each block is 2x2 steps)
+    pub height: u8,
+    /// The width of the map in blocks (each block is 2x2 steps)
+    pub width: u8,
+    pub north: Option<MapConnection>,
+    pub south: Option<MapConnection>,
+    pub west: Option<MapConnection>,
+    pub east: Option<MapConnection>,
+}
+
+impl MapHeader {
+    /// Returns the ids of every map connected to this map
+    pub fn connected_maps(&self) -> Vec<u8> {
+        [self.north, self.south, self.west, self.east].iter().flatten().map(|c| c.map_id).collect()
+    }
+
+    /// Translate the position of a player into the coordinate space of this map. Returns `None` if
+    /// the player is not on this map or on a map connected to it.
+    pub fn translate(&self, movement: &MovementData) -> Option<(i32, i32)> {
+        let (x, y) = (movement.map_x as i32, movement.map_y as i32);
+        if movement.map_id == self.map_id {
+            return Some((x, y));
+        }
+
+        // The alignment along the direction of the connection is the coordinate that the player
+        // ends up at after crossing, while the alignment across the connection is a signed offset.
+        let is_connection = |c: &Option<MapConnection>| c.filter(|c| c.map_id == movement.map_id);
+        if let Some(c) = is_connection(&self.north) {
+            Some((x - c.x_align as i8 as i32, y - c.y_align as i32 - 1))
+        }
+        else if let Some(c) = is_connection(&self.south) {
+            Some((x - c.x_align as i8 as i32, y - c.y_align as i32 + self.height as i32 * 2))
+        }
+        else if let Some(c) = is_connection(&self.west) {
+            Some((x - c.x_align as i32 - 1, y - c.y_align as i8 as i32))
+        }
+        else if let Some(c) = is_connection(&self.east) {
+            Some((x - c.x_align as i32 + self.width as i32 * 2, y - c.y_align as i8 as i32))
+        }
+        else {
+            None
+        }
+    }
+}
+
 /// The sprite data for a 16x16 sprite
 #[derive(Clone, Copy)]
 pub struct SpriteData {
@@ -50,6 +120,8 @@ pub struct PlayerData {
     pub sprite: Vec<u8>,
     // pub sprite_data: SpriteData,
     pub movement_data: MovementData,
+    /// Shown to other players when they talk to this player
+    pub greeting: String,
 }
 
 impl PlayerData {
@@ -58,19 +130,25 @@ impl PlayerData {
             name: vec![],
             sprite: extract::default_sprite(mem),
             movement_data: MovementData::new(),
+            greeting: String::new(),
         }
     }
 
+    /// The message shown when talking to this player, with their name filled in
+    pub fn greeting_message(&self) -> String {
+        let greeting = match self.greeting.trim() {
+            "" => DEFAULT_GREETING,
+            greeting => greeting,
+        };
+        let greeting: String = greeting.chars().take(MAX_GREETING_LENGTH).collect();
+        greeting.replace(GREETING_NAME, &text::decode(&self.name))
+    }
+
     /// Check if this player is occupying a particular tile
     pub fn check_collision(&self, x: u8, y: u8) -> bool {
         (x, y) == (self.movement_data.map_x, self.movement_data.map_y)
             || (x, y) == self.movement_data.move_target()
     }
-
-    /// Check if one player is visible to another player
-    pub fn is_visible_to(&self, other: &PlayerData) -> bool {
-        self.movement_data.map_id == other.movement_data.map_id
-    }
 }
 
 #[derive(Clone, serde::Serialize, serde::Deserialize)]
@@ -79,10 +157,169 @@ pub struct Party {
     pub pokemon: (PokemonData, PokemonData, PokemonData, PokemonData, PokemonData, PokemonData),
 }
 
+impl Party {
+    /// The pokemon that are actually in the party
+    pub fn members(&self) -> impl Iterator<Item = &PokemonData> {
+        let (a, b, c, d, e, f) = &self.pokemon;
+        [a, b, c, d, e, f].into_iter().take(self.num_pokemon as usize)
+    }
+}
+
 pub const BATTLE_DATA_SIZE: usize = 0x194;
 pub type BattleData = Vec<u8>;
 
-#[derive(Clone, serde::Serialize, serde::Deserialize)]
+/// A single pokemon along with the names stored next to the party, everything that needs to be
+/// sent when a pokemon moves from one game to another.
+#[derive(Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct PokemonRecord {
+    pub data: PokemonData,
+    pub ot_name: Vec<u8>,
+    pub nickname: Vec<u8>,
+}
+
+/// A species, with its name decoded so that it can be shown outside of the game
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct Species {
+    /// The id used by the game internally
+    pub id: u8,
+    /// The number of the species in the pokedex, 0 for ids that are not used by a real species
+    pub dex_number: u8,
+    pub name: String,
+}
+
+/// Anything else that the game refers to by an id, along with its decoded name
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct Named {
+    pub id: u8,
+    pub name: String,
+}
+
+/// The names of everything that the game refers to by an id, read from the rom by
+/// `extract::name_tables`
+#[derive(Clone, Debug, Default, serde::Serialize, serde::Deserialize)]
+pub struct NameTables {
+    pub species: Vec<Species>,
+    pub moves: Vec<Named>,
+    pub types: Vec<Named>,
+    pub items: Vec<Named>,
+    pub trainer_classes: Vec<Named>,
+}
+
+impl NameTables {
+    pub fn species(&self, id: u8) -> Option<&Species> {
+        self.species.iter().find(|species| species.id == id)
+    }
+
+    pub fn species_name(&self, id: u8) -> &str {
+        self.species(id).map_or(UNKNOWN_NAME, |species| &species.name)
+    }
+
+    pub fn move_name(&self, id: u8) -> &str {
+        find_name(&self.moves, id)
+    }
+
+    pub fn type_name(&self, id: u8) -> &str {
+        find_name(&self.types, id)
+    }
+
+    pub fn item_name(&self, id: u8) -> &str {
+        find_name(&self.items, id)
+    }
+
+    pub fn trainer_class_name(&self, id: u8) -> &str {
+        find_name(&self.trainer_classes, id)
+    }
+}
+
+/// The name given to ids that are not in a table
+const UNKNOWN_NAME: &str = "???";
+
+fn find_name(entries: &[Named], id: u8) -> &str {
+    entries.iter().find(|entry| entry.id == id).map_or(UNKNOWN_NAME, |entry| &entry.name)
+}
+
+/// A species' base stats entry, without the parts that describe its sprites
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct BaseStats {
+    pub hp: u8,
+    pub attack: u8,
+    pub defense: u8,
+    pub speed: u8,
+    pub special: u8,
+    pub type1: u8,
+    pub type2: u8,
+    pub catch_rate: u8,
+    pub base_exp: u8,
+    /// The moves known by the species at level 1, padded with `moves::NONE`
+    pub starting_moves: [u8; 4],
+    pub growth_rate: u8,
+    /// One bit for each TM, followed by the HMs, that the species can learn
+    pub machines: [u8; 7],
+}
+
+impl BaseStats {
+    /// Whether the species can be taught the move in a TM or HM, `machine` counts from 0 starting
+    /// with TM01 and the HMs come after the last TM
+    pub fn can_learn_machine(&self, machine: u8) -> bool {
+        let byte = self.machines.get(machine as usize / 8).copied().unwrap_or(0);
+        byte & (1 << (machine % 8)) != 0
+    }
+}
+
+/// An entry in the table of moves
+#[derive(Copy, Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct MoveData {
+    pub id: u8,
+    pub effect: u8,
+    pub power: u8,
+    pub move_type: u8,
+    /// The chance of the move hitting out of 255
+    pub accuracy: u8,
+    pub pp: u8,
+}
+
+/// An entry in a species' list of evolutions
+#[derive(Copy, Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Evolution {
+    /// One of the values in `values::evolution`
+    pub kind: u8,
+    /// The level needed to evolve, which is 1 for evolutions that are not caused by levelling up
+    pub level: u8,
+    /// The item used to evolve, or 0 if no item is needed
+    pub item: u8,
+    pub species: u8,
+}
+
+/// The moves that a species can learn
+#[derive(Clone, Debug, Default, serde::Serialize, serde::Deserialize)]
+pub struct Learnset {
+    pub starting_moves: Vec<u8>,
+    /// Moves learned by levelling up, as (level, move)
+    pub level_moves: Vec<(u8, u8)>,
+    /// Moves that can be taught with a TM or HM
+    pub machine_moves: Vec<u8>,
+}
+
+impl Learnset {
+    /// Whether a pokemon of this species could know `move_id` at `level`
+    pub fn can_learn(&self, move_id: u8, level: u8) -> bool {
+        self.starting_moves.contains(&move_id)
+            || self.machine_moves.contains(&move_id)
+            || self.level_moves.iter().any(|&(min_level, id)| id == move_id && min_level <= level)
+    }
+}
+
+/// The stats of a pokemon that depend on its level
+#[derive(Copy, Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Stats {
+    pub max_hp: u16,
+    pub attack: u16,
+    pub defense: u16,
+    pub speed: u16,
+    pub special: u16,
+}
+
+#[derive(Clone, PartialEq, serde::Serialize, serde::Deserialize)]
 #[allow(missing_copy_implementations)]
 pub struct PokemonData {
     pub species: u8,
@@ -113,6 +350,58 @@ pub struct PokemonData {
 }
 
 impl PokemonData {
+    /// Work out the stats of this pokemon from its species' base stats, its level, DVs and stat
+    /// experience in the same way as the game does
+    pub fn calculate_stats(&self, base: &BaseStats) -> Stats {
+        let (attack_defense, speed_special) = self.individual_values;
+        let attack_dv = attack_defense >> 4;
+        let defense_dv = attack_defense & 0xF;
+        let speed_dv = speed_special >> 4;
+        let special_dv = speed_special & 0xF;
+        let hp_dv =
+            (attack_dv & 1) << 3 | (defense_dv & 1) << 2 | (speed_dv & 1) << 1 | (special_dv & 1);
+
+        Stats {
+            max_hp: self.calculate_stat(base.hp, hp_dv, self.hp_ev) + self.level as u16 + 5,
+            attack: self.calculate_stat(base.attack, attack_dv, self.attack_ev),
+            defense: self.calculate_stat(base.defense, defense_dv, self.defense_ev),
+            speed: self.calculate_stat(base.speed, speed_dv, self.speed_ev),
+            special: self.calculate_stat(base.special, special_dv, self.special_ev),
+        }
+    }
+
+    /// The stats currently stored for this pokemon
+    pub fn stats(&self) -> Stats {
+        Stats {
+            max_hp: self.max_hp,
+            attack: self.attack,
+            defense: self.defense,
+            speed: self.speed,
+            special: self.special,
+        }
+    }
+
+    /// Recompute the stats of this pokemon with `calculate_stats`. Damage that the pokemon has
+    /// taken is kept.
+    pub fn recalculate_stats(&mut self, base: &BaseStats) {
+        let stats = self.calculate_stats(base);
+        let damage = self.max_hp.saturating_sub(self.hp);
+        self.max_hp = stats.max_hp;
+        self.hp = self.max_hp.saturating_sub(damage);
+
+        self.attack = stats.attack;
+        self.defense = stats.defense;
+        self.speed = stats.speed;
+        self.special = stats.special;
+    }
+
+    fn calculate_stat(&self, base: u8, dv: u8, stat_exp: u16) -> u16 {
+        // The game uses the smallest number whose square is at least the stat experience
+        let stat_exp_bonus = (0..=255_u32).find(|n| n * n >= stat_exp as u32).unwrap_or(255);
+        let value = ((base as u32 + dv as u32) * 2 + stat_exp_bonus / 4) * self.level as u32;
+        (value / 100 + 5) as u16
+    }
+
     pub fn test_data() -> PokemonData {
         PokemonData {
             species: pokeid::WEEDLE,
@@ -143,3 +432,65 @@ impl PokemonData {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_rom;
+
+    fn base_stats(stats: [u8; 5]) -> BaseStats {
+        test_rom::base_stats(stats, types::NORMAL, types::NORMAL)
+    }
+
+    #[test]
+    fn stats_of_known_pokemon() {
+        // A level 81 Pikachu, with DVs of 8/13/5/9 (giving an HP DV of 7)
+        let mut pokemon = PokemonData::test_data();
+        pokemon.level = 81;
+        pokemon.individual_values = (0x8D, 0x59);
+        pokemon.hp_ev = 22850;
+        pokemon.attack_ev = 23140;
+        pokemon.defense_ev = 17280;
+        pokemon.speed_ev = 24795;
+        pokemon.special_ev = 19625;
+
+        let stats = pokemon.calculate_stats(&base_stats([35, 55, 30, 90, 50]));
+        let expected = Stats { max_hp: 189, attack: 137, defense: 101, speed: 190, special: 128 };
+        assert_eq!(stats, expected);
+    }
+
+    #[test]
+    fn stats_with_no_stat_exp() {
+        // A freshly caught level 5 pokemon with the lowest DVs
+        let mut pokemon = PokemonData::test_data();
+        pokemon.level = 5;
+        pokemon.individual_values = (0x00, 0x00);
+
+        let stats = pokemon.calculate_stats(&base_stats([40, 35, 30, 50, 20]));
+        let expected = Stats { max_hp: 19, attack: 8, defense: 8, speed: 10, special: 7 };
+        assert_eq!(stats, expected);
+    }
+
+    #[test]
+    fn stat_exp_bonus_is_capped() {
+        // Chansey's highest possible HP
+        let mut pokemon = PokemonData::test_data();
+        pokemon.level = 100;
+        pokemon.individual_values = (0xFF, 0xFF);
+        pokemon.hp_ev = u16::MAX;
+        assert_eq!(pokemon.calculate_stats(&base_stats([250, 5, 5, 50, 105])).max_hp, 703);
+    }
+
+    #[test]
+    fn recalculating_stats_keeps_damage() {
+        let mut pokemon = PokemonData::test_data();
+        pokemon.level = 81;
+        pokemon.individual_values = (0x8D, 0x59);
+        pokemon.max_hp = 150;
+        pokemon.hp = 140;
+
+        pokemon.recalculate_stats(&base_stats([35, 55, 30, 90, 50]));
+        assert_eq!(pokemon.max_hp, 159);
+        assert_eq!(pokemon.hp, 149);
+    }
+}
@@ -4,25 +4,32 @@ use gb_emu::graphics;
 use gb_emu::mmu::Memory;
 
 use crate::{
-    data::{BattleData, MovementData, Party, PokemonData, BATTLE_DATA_SIZE},
+    data::{
+        BaseStats, BattleData, Evolution, Learnset, MapConnection, MapHeader, MoveData,
+        MovementData, NameTables, Named, Party, PokemonData, PokemonRecord, Species,
+        BATTLE_DATA_SIZE,
+    },
     offsets, text,
-    values::Direction,
+    values::{
+        evolution, items, moves, pokeid, types, Direction, NUM_MOVES, NUM_SPECIES,
+        NUM_TRAINER_CLASSES,
+    },
 };
 
 pub fn movement_data(mem: &Memory) -> MovementData {
     MovementData {
-        map_id: mem.lb(offsets::MAP_ID),
-        map_x: mem.lb(offsets::MAP_X),
-        map_y: mem.lb(offsets::MAP_Y),
-        direction: Direction::from_u8(mem.lb(offsets::PLAYER_DIR)).unwrap_or(Direction::Down),
-        walk_counter: mem.lb(offsets::WALK_COUNTER),
+        map_id: mem.lb(offsets::get().map_id),
+        map_x: mem.lb(offsets::get().map_x),
+        map_y: mem.lb(offsets::get().map_y),
+        direction: Direction::from_u8(mem.lb(offsets::get().player_dir)).unwrap_or(Direction::Down),
+        walk_counter: mem.lb(offsets::get().walk_counter),
     }
 }
 
 pub fn player_name(mem: &Memory) -> Vec<u8> {
     let mut name = vec![];
 
-    let mut offset = offsets::PLAYER_NAME_START;
+    let mut offset = offsets::get().player_name_start;
     for _ in 0..11 {
         match mem.lb(offset) {
             text::special::TERMINATOR => break,
@@ -34,58 +41,408 @@ pub fn player_name(mem: &Memory) -> Vec<u8> {
 }
 
 pub fn battle_data(mem: &Memory) -> BattleData {
-    let base_offset = offsets::PLAYER_BATTLE_DATA_START;
+    let base_offset = offsets::get().player_battle_data_start;
     (0..BATTLE_DATA_SIZE as u16).map(|i| mem.lb(base_offset + i)).collect()
 }
 
 fn pokemon_data(mem: &Memory, addr: u16) -> PokemonData {
     PokemonData {
-        species: mem.lb(addr + 0),
-        hp: mem.lw(addr + 1),
+        species: mem.lb(addr),
+        hp: big_endian_word(mem, addr + 1),
         unknown: mem.lb(addr + 3),
         status: mem.lb(addr + 4),
         type1: mem.lb(addr + 5),
         type2: mem.lb(addr + 6),
         catch_rate: mem.lb(addr + 7),
         moves: (mem.lb(addr + 8), mem.lb(addr + 9), mem.lb(addr + 10), mem.lb(addr + 11)),
-        ot_id: mem.lw(addr + 12),
+        ot_id: big_endian_word(mem, addr + 12),
 
         exp: (mem.lb(addr + 14), mem.lb(addr + 15), mem.lb(addr + 16)),
-        hp_ev: mem.lw(addr + 17),
-        attack_ev: mem.lw(addr + 19),
-        defense_ev: mem.lw(addr + 21),
-        speed_ev: mem.lw(addr + 23),
-        special_ev: mem.lw(addr + 25),
+        hp_ev: big_endian_word(mem, addr + 17),
+        attack_ev: big_endian_word(mem, addr + 19),
+        defense_ev: big_endian_word(mem, addr + 21),
+        speed_ev: big_endian_word(mem, addr + 23),
+        special_ev: big_endian_word(mem, addr + 25),
         individual_values: (mem.lb(addr + 27), mem.lb(addr + 28)),
         move_pp: (mem.lb(addr + 29), mem.lb(addr + 30), mem.lb(addr + 31), mem.lb(addr + 32)),
 
         level: mem.lb(addr + 33),
-        max_hp: mem.lw(addr + 34),
-        attack: mem.lw(addr + 36),
-        defense: mem.lw(addr + 38),
-        speed: mem.lw(addr + 40),
-        special: mem.lw(addr + 42),
+        max_hp: big_endian_word(mem, addr + 34),
+        attack: big_endian_word(mem, addr + 36),
+        defense: big_endian_word(mem, addr + 38),
+        speed: big_endian_word(mem, addr + 40),
+        special: big_endian_word(mem, addr + 42),
     }
 }
 
-// Currently this has been changed to use a more specific method, however we may want to use this
-// for other things in the future. (e.g. server trainers)
+/// Pokemon data is stored big-endian, unlike the values that the CPU reads directly
+fn big_endian_word(mem: &Memory, addr: u16) -> u16 {
+    (mem.lb(addr) as u16) << 8 | mem.lb(addr + 1) as u16
+}
+
+/// Read a name terminated by `TERMINATOR` that takes up at most `length` bytes
+fn name(mem: &Memory, addr: u16, length: u16) -> Vec<u8> {
+    (addr..addr + length)
+        .map(|addr| mem.lb(addr))
+        .take_while(|&val| val != text::special::TERMINATOR)
+        .collect()
+}
+
+/// Read the nickname of every pokemon in the party
+pub fn player_party_names(mem: &Memory) -> Vec<Vec<u8>> {
+    let count = mem.lb(offsets::get().party_count).min(6) as u16;
+    (0..count).map(|slot| party_pokemon_name(mem, offsets::get().party_nicknames, slot)).collect()
+}
+
+/// Read the pokemon in a party slot, along with its names
+pub fn party_pokemon(mem: &Memory, slot: u8) -> PokemonRecord {
+    let slot = slot as u16;
+    PokemonRecord {
+        data: pokemon_data(mem, offsets::get().party_poke_1 + slot * offsets::PARTY_POKE_SIZE),
+        ot_name: party_pokemon_name(mem, offsets::get().party_ot_names, slot),
+        nickname: party_pokemon_name(mem, offsets::get().party_nicknames, slot),
+    }
+}
+
+/// Read the name of a party pokemon from one of the party's name lists
+fn party_pokemon_name(mem: &Memory, names: u16, slot: u16) -> Vec<u8> {
+    name(mem, names + slot * offsets::NAME_LENGTH, offsets::NAME_LENGTH)
+}
+
+/// Read the whole party, without the names of the pokemon
 pub fn player_party(mem: &Memory) -> Party {
     Party {
-        num_pokemon: mem.lb(offsets::PARTY_COUNT),
+        num_pokemon: mem.lb(offsets::get().party_count),
         pokemon: (
-            pokemon_data(mem, offsets::PARTY_POKE_1),
-            pokemon_data(mem, offsets::PARTY_POKE_2),
-            pokemon_data(mem, offsets::PARTY_POKE_3),
-            pokemon_data(mem, offsets::PARTY_POKE_4),
-            pokemon_data(mem, offsets::PARTY_POKE_5),
-            pokemon_data(mem, offsets::PARTY_POKE_6),
+            pokemon_data(mem, offsets::get().party_poke_1),
+            pokemon_data(mem, offsets::get().party_poke_2),
+            pokemon_data(mem, offsets::get().party_poke_3),
+            pokemon_data(mem, offsets::get().party_poke_4),
+            pokemon_data(mem, offsets::get().party_poke_5),
+            pokemon_data(mem, offsets::get().party_poke_6),
         ),
     }
 }
 
+/// The contents of a cartridge. Data can be read from the rom either while the game is running or
+/// straight from a rom file.
+pub trait Rom {
+    /// Read a byte from the rom, `addr` is the address as seen by the CPU when `bank` is loaded.
+    fn rom_byte(&self, bank: usize, addr: u16) -> u8;
+}
+
+impl Rom for Memory {
+    fn rom_byte(&self, bank: usize, addr: u16) -> u8 {
+        match addr {
+            0x0000..=0x3FFF => self.cart.rom[0][addr as usize],
+            _ => self.cart.rom[bank][(addr & 0x3FFF) as usize],
+        }
+    }
+}
+
+/// The contents of a rom file, reading past the end of the file gives 0
+impl Rom for [u8] {
+    fn rom_byte(&self, bank: usize, addr: u16) -> u8 {
+        let offset = match addr {
+            0x0000..=0x3FFF => addr as usize,
+            _ => bank * 0x4000 + (addr & 0x3FFF) as usize,
+        };
+        self.get(offset).copied().unwrap_or(0)
+    }
+}
+
+fn rom_byte<R: Rom + ?Sized>(rom: &R, bank: usize, addr: u16) -> u8 {
+    rom.rom_byte(bank, addr)
+}
+
+fn rom_word<R: Rom + ?Sized>(rom: &R, bank: usize, addr: u16) -> u16 {
+    rom_byte(rom, bank, addr) as u16 | (rom_byte(rom, bank, addr + 1) as u16) << 8
+}
+
+/// Read a string from the rom that ends with a terminator, returning it along with the address
+/// just after the terminator. Strings in a rom that is not the one we expect may never end, so
+/// they are cut off after `MAX_ROM_STRING` characters.
+fn rom_string<R: Rom + ?Sized>(rom: &R, bank: usize, addr: u16) -> (Vec<u8>, u16) {
+    const MAX_ROM_STRING: usize = 32;
+
+    let mut string = vec![];
+    let mut addr = addr;
+    while string.len() < MAX_ROM_STRING {
+        let val = rom_byte(rom, bank, addr);
+        addr = addr.wrapping_add(1);
+        if val == text::special::TERMINATOR {
+            return (string, addr);
+        }
+        string.push(val);
+    }
+    (string, addr)
+}
+
+/// Read `count` strings that are stored one after another in the rom
+fn rom_string_list<R: Rom + ?Sized>(rom: &R, bank: usize, addr: u16, count: u8) -> Vec<Vec<u8>> {
+    let mut addr = addr;
+    (0..count)
+        .map(|_| {
+            let (string, next) = rom_string(rom, bank, addr);
+            addr = next;
+            string
+        })
+        .collect()
+}
+
+/// Returns the pokedex number of a species, or 0 if the species is not a valid pokemon
+pub fn pokedex_number<R: Rom + ?Sized>(rom: &R, species: u8) -> u8 {
+    match species {
+        1..=NUM_SPECIES => {
+            let addr = offsets::get().pokedex_order + species as u16 - 1;
+            rom_byte(rom, offsets::get().pokedex_order_bank, addr)
+        }
+        _ => 0,
+    }
+}
+
+/// Read the base stats of a species from the rom
+pub fn base_stats<R: Rom + ?Sized>(rom: &R, species: u8) -> BaseStats {
+    let (bank, addr) = match species {
+        pokeid::MEW => (offsets::get().mew_base_stats_bank, offsets::get().mew_base_stats),
+        _ => {
+            let index = (pokedex_number(rom, species) as u16).saturating_sub(1);
+            let addr = offsets::get().base_stats + index * offsets::BASE_STATS_SIZE;
+            (offsets::get().base_stats_bank, addr)
+        }
+    };
+
+    BaseStats {
+        hp: rom_byte(rom, bank, addr + 1),
+        attack: rom_byte(rom, bank, addr + 2),
+        defense: rom_byte(rom, bank, addr + 3),
+        speed: rom_byte(rom, bank, addr + 4),
+        special: rom_byte(rom, bank, addr + 5),
+        type1: rom_byte(rom, bank, addr + 6),
+        type2: rom_byte(rom, bank, addr + 7),
+        catch_rate: rom_byte(rom, bank, addr + 8),
+        base_exp: rom_byte(rom, bank, addr + 9),
+        starting_moves: [0, 1, 2, 3].map(|i| rom_byte(rom, bank, addr + 15 + i)),
+        growth_rate: rom_byte(rom, bank, addr + 19),
+        machines: [0, 1, 2, 3, 4, 5, 6].map(|i| rom_byte(rom, bank, addr + 20 + i)),
+    }
+}
+
+/// Read the evolutions of a species, along with the address of its level up moves which are
+/// stored straight after them
+fn evolutions_and_moves_addr<R: Rom + ?Sized>(rom: &R, species: u8) -> (Vec<Evolution>, u16) {
+    let bank = offsets::get().evos_moves_bank;
+    let pointer = offsets::get().evos_moves_pointers + (species as u16 - 1) * 2;
+    let mut addr = rom_word(rom, bank, pointer);
+
+    // Each entry starts with the kind of evolution, item evolutions have an extra byte for the
+    // item before the level and the species to evolve into.
+    let mut evolutions = vec![];
+    loop {
+        let kind = rom_byte(rom, bank, addr);
+        let item = match kind {
+            evolution::END => return (evolutions, addr.wrapping_add(1)),
+            evolution::ITEM => {
+                addr = addr.wrapping_add(1);
+                rom_byte(rom, bank, addr)
+            }
+            _ => 0,
+        };
+        evolutions.push(Evolution {
+            kind,
+            level: rom_byte(rom, bank, addr.wrapping_add(1)),
+            item,
+            species: rom_byte(rom, bank, addr.wrapping_add(2)),
+        });
+        addr = addr.wrapping_add(3);
+    }
+}
+
+/// Read the ways that a species can evolve from the rom
+pub fn evolutions<R: Rom + ?Sized>(rom: &R, species: u8) -> Vec<Evolution> {
+    match species {
+        1..=NUM_SPECIES => evolutions_and_moves_addr(rom, species).0,
+        _ => vec![],
+    }
+}
+
+/// Returns the species that `species` evolves into when it is traded, if any
+pub fn trade_evolution<R: Rom + ?Sized>(rom: &R, species: u8) -> Option<u8> {
+    let mut evolutions = evolutions(rom, species).into_iter();
+    evolutions.find(|e| e.kind == evolution::TRADE).map(|e| e.species)
+}
+
+/// Read the moves that a species can learn from the rom. This does not include moves that can only
+/// be learned before evolving.
+pub fn learnset<R: Rom + ?Sized>(rom: &R, species: u8) -> Learnset {
+    if species == 0 || species > NUM_SPECIES {
+        return Learnset::default();
+    }
+
+    let base = base_stats(rom, species);
+    let starting_moves = base.starting_moves.into_iter().filter(|&id| id != moves::NONE).collect();
+
+    // Level up moves are stored as (level, move) pairs, ending with a 0
+    let bank = offsets::get().evos_moves_bank;
+    let mut addr = evolutions_and_moves_addr(rom, species).1;
+    let mut level_moves = vec![];
+    loop {
+        let level = rom_byte(rom, bank, addr);
+        if level == 0 {
+            break;
+        }
+        level_moves.push((level, rom_byte(rom, bank, addr.wrapping_add(1))));
+        addr = addr.wrapping_add(2);
+    }
+
+    let machine_moves = (0..items::NUM_TMS + items::NUM_HMS)
+        .filter(|&machine| base.can_learn_machine(machine))
+        .map(|machine| machine_move(rom, machine))
+        .collect();
+
+    Learnset { starting_moves, level_moves, machine_moves }
+}
+
+/// Returns the move taught by a TM or HM, `machine` counts from 0 starting with TM01 and the HMs
+/// come after the last TM
+pub fn machine_move<R: Rom + ?Sized>(rom: &R, machine: u8) -> u8 {
+    let addr = offsets::get().technical_machines + machine as u16;
+    rom_byte(rom, offsets::get().technical_machines_bank, addr)
+}
+
+/// Read the entry for a move from the table of moves in the rom
+pub fn move_data<R: Rom + ?Sized>(rom: &R, move_id: u8) -> Option<MoveData> {
+    if move_id == moves::NONE || move_id > NUM_MOVES {
+        return None;
+    }
+
+    let bank = offsets::get().moves_bank;
+    let addr = offsets::get().moves + (move_id as u16 - 1) * offsets::MOVE_SIZE;
+    Some(MoveData {
+        id: rom_byte(rom, bank, addr),
+        effect: rom_byte(rom, bank, addr + 1),
+        power: rom_byte(rom, bank, addr + 2),
+        move_type: rom_byte(rom, bank, addr + 3),
+        accuracy: rom_byte(rom, bank, addr + 4),
+        pp: rom_byte(rom, bank, addr + 5),
+    })
+}
+
+/// Read every move from the table of moves in the rom
+pub fn move_table<R: Rom + ?Sized>(rom: &R) -> Vec<MoveData> {
+    (1..=NUM_MOVES).filter_map(|id| move_data(rom, id)).collect()
+}
+
+/// Read the default name of a species from the rom
+pub fn species_name<R: Rom + ?Sized>(rom: &R, species: u8) -> Vec<u8> {
+    let index = (species as u16).saturating_sub(1);
+    let addr = offsets::get().monster_names + index * offsets::MONSTER_NAME_LENGTH;
+    (addr..addr + offsets::MONSTER_NAME_LENGTH)
+        .map(|addr| rom_byte(rom, offsets::get().monster_names_bank, addr))
+        .take_while(|&val| val != text::special::TERMINATOR)
+        .collect()
+}
+
+/// Read the name of a move from the rom
+pub fn move_name<R: Rom + ?Sized>(rom: &R, move_id: u8) -> Vec<u8> {
+    let bank = offsets::get().move_names_bank;
+    let mut addr = offsets::get().move_names;
+    for _ in 1..move_id {
+        addr = rom_string(rom, bank, addr).1;
+    }
+    rom_string(rom, bank, addr).0
+}
+
+/// Read the names of every species, move, type, item and trainer class from the rom
+pub fn name_tables<R: Rom + ?Sized>(rom: &R) -> NameTables {
+    let species = (1..=NUM_SPECIES)
+        .map(|id| Species {
+            id,
+            dex_number: pokedex_number(rom, id),
+            name: text::decode(&species_name(rom, id)),
+        })
+        .collect();
+
+    let offsets = offsets::get();
+    let moves = rom_string_list(rom, offsets.move_names_bank, offsets.move_names, NUM_MOVES);
+
+    // Unused types are filled in with pointers to NORMAL, so they are left out
+    let types = (0..=types::DRAGON)
+        .filter(|id| !types::UNUSED.contains(id))
+        .map(|id| {
+            let pointer = offsets.type_names + id as u16 * 2;
+            let addr = rom_word(rom, offsets.type_names_bank, pointer);
+            (id, rom_string(rom, offsets.type_names_bank, addr).0)
+        });
+
+    // The names of TMs and HMs are not stored in the rom, the game makes them up from the number
+    let items = rom_string_list(rom, offsets.item_names_bank, offsets.item_names, items::COUNT);
+    let machines = (0..items::NUM_HMS)
+        .map(|i| (items::FIRST_HM + i, format!("HM{:02}", i + 1)))
+        .chain((0..items::NUM_TMS).map(|i| (items::FIRST_TM + i, format!("TM{:02}", i + 1))));
+
+    let (bank, addr) = (offsets.trainer_names_bank, offsets.trainer_names);
+    let trainer_classes = rom_string_list(rom, bank, addr, NUM_TRAINER_CLASSES);
+
+    NameTables {
+        species,
+        moves: named_from_one(moves),
+        types: types.map(|(id, name)| Named { id, name: text::decode(&name) }).collect(),
+        items: named_from_one(items)
+            .into_iter()
+            .chain(machines.map(|(id, name)| Named { id, name }))
+            .collect(),
+        trainer_classes: named_from_one(trainer_classes),
+    }
+}
+
+/// Give each name in a list read from the rom its id, with the first name having an id of 1
+fn named_from_one(names: Vec<Vec<u8>>) -> Vec<Named> {
+    (1..).zip(names).map(|(id, name)| Named { id, name: text::decode(&name) }).collect()
+}
+
+/// Read the header of a map from the rom
+pub fn map_header<R: Rom + ?Sized>(rom: &R, map_id: u8) -> MapHeader {
+    const NORTH: u8 = 1 << 3;
+    const SOUTH: u8 = 1 << 2;
+    const WEST: u8 = 1 << 1;
+    const EAST: u8 = 1 << 0;
+    const CONNECTION_SIZE: u16 = 11;
+
+    let bank_addr = offsets::get().map_header_banks + map_id as u16;
+    let bank = rom_byte(rom, offsets::get().map_header_banks_bank, bank_addr) as usize;
+    let addr = rom_word(rom, 0, offsets::get().map_header_pointers + 2 * map_id as u16);
+
+    // The connections are stored after the fixed part of the header, but only the connections
+    // that are present are stored.
+    let flags = rom_byte(rom, bank, addr + 9);
+    let mut connection_addr = addr + 10;
+    let mut read_connection = |direction| {
+        if flags & direction == 0 {
+            return None;
+        }
+        let connection = MapConnection {
+            map_id: rom_byte(rom, bank, connection_addr),
+            y_align: rom_byte(rom, bank, connection_addr + 7),
+            x_align: rom_byte(rom, bank, connection_addr + 8),
+        };
+        connection_addr += CONNECTION_SIZE;
+        Some(connection)
+    };
+
+    MapHeader {
+        map_id,
+        height: rom_byte(rom, bank, addr + 1),
+        width: rom_byte(rom, bank, addr + 2),
+        north: read_connection(NORTH),
+        south: read_connection(SOUTH),
+        west: read_connection(WEST),
+        east: read_connection(EAST),
+    }
+}
+
 pub fn default_sprite(mem: &Memory) -> Vec<u8> {
-    extract_sprite(mem, offsets::BLUE_SPRITE_BANK, offsets::BLUE_SPRITE_ADDR)
+    extract_sprite(mem, offsets::get().blue_sprite_bank, offsets::get().blue_sprite_addr)
 }
 
 const TILE_SIZE: usize = 8;
@@ -196,3 +553,57 @@ pub fn extract_texture(
 
     output_buffer
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_rom::{
+        TestRom, GROWL, PIKACHU, QUICK_ATTACK, RAICHU, FLASH, THUNDERSHOCK, THUNDER_STONE,
+        THUNDER_WAVE,
+    };
+
+    /// A rom that only contains the entries for Pikachu
+    fn pikachu_rom() -> TestRom {
+        let mut rom = TestRom::new();
+        rom.add_pikachu();
+        rom
+    }
+
+    #[test]
+    fn read_base_stats() {
+        let base = base_stats(&pikachu_rom(), PIKACHU);
+        assert_eq!((base.hp, base.attack, base.speed), (35, 55, 90));
+        assert_eq!((base.type1, base.type2), (types::ELECTRIC, types::ELECTRIC));
+        assert!(base.can_learn_machine(0));
+        assert!(!base.can_learn_machine(1));
+    }
+
+    #[test]
+    fn read_learnset() {
+        let learnset = learnset(&pikachu_rom(), PIKACHU);
+        assert_eq!(learnset.starting_moves, [THUNDERSHOCK, GROWL]);
+        assert_eq!(learnset.level_moves, [(9, THUNDER_WAVE), (16, QUICK_ATTACK)]);
+        assert_eq!(learnset.machine_moves, [moves::MEGA_PUNCH, FLASH]);
+
+        assert!(learnset.can_learn(GROWL, 2));
+        assert!(learnset.can_learn(THUNDER_WAVE, 9));
+        assert!(!learnset.can_learn(QUICK_ATTACK, 15));
+        assert!(!learnset.can_learn(moves::POUND, 100));
+    }
+
+    #[test]
+    fn read_evolutions() {
+        let rom = pikachu_rom();
+        let expected =
+            Evolution { kind: evolution::ITEM, level: 1, item: THUNDER_STONE, species: RAICHU };
+        assert_eq!(evolutions(&rom, PIKACHU), [expected]);
+        assert_eq!(trade_evolution(&rom, PIKACHU), None);
+    }
+
+    #[test]
+    fn invalid_species_has_no_learnset() {
+        let rom = pikachu_rom();
+        assert!(learnset(&rom, 0).starting_moves.is_empty());
+        assert!(learnset(&rom, NUM_SPECIES + 1).level_moves.is_empty());
+    }
+}
@@ -1,22 +1,34 @@
+use std::time::Instant;
+
 use gb_emu::cpu::Cpu;
 use gb_emu::mmu::Memory;
 
-use super::{InterfaceData, InterfaceState, DataState, NetworkRequest, offsets, text};
+use super::{Dialog, InterfaceData, DataState, NetworkRequest, offsets, values};
 
 pub fn sprite_check(cpu: &mut Cpu, mem: &mut Memory, interface_data: &mut InterfaceData) {
-    if cpu.pc == offsets::OVERWORLD_LOOP_START {
+    if cpu.pc == offsets::get().overworld_loop_start {
         interface_data.sprite_id_state = DataState::Normal;
+        interface_data.press_for_request(mem);
     }
 
-    if (cpu.pc == offsets::SPRITE_CHECK_EXIT_1 && mem.lb(offsets::NUM_SPRITES) == 0) ||
-        cpu.pc == offsets::SPRITE_CHECK_EXIT_2
+    if (cpu.pc == offsets::get().sprite_check_exit_1 && mem.lb(offsets::get().num_sprites) == 0) ||
+        cpu.pc == offsets::get().sprite_check_exit_2
     {
-        let map_id = mem.lb(offsets::MAP_ID);
+        // A request from another player is shown as if the player had talked to them, whatever is
+        // in front of the player
+        if let Some(from) = interface_data.request_pressed() {
+            mem.sb(offsets::get().sprite_index, 0xFF);
+            interface_data.sprite_id_state = DataState::Hacked;
+            interface_data.last_interaction = from;
+            return;
+        }
+
+        let map_id = mem.lb(offsets::get().map_id);
 
         // Determine the tile that the player is trying to move into.
-        let mut x = mem.lb(offsets::MAP_X);
-        let mut y = mem.lb(offsets::MAP_Y);
-        match mem.lb(offsets::PLAYER_DIR) {
+        let mut x = mem.lb(offsets::get().map_x);
+        let mut y = mem.lb(offsets::get().map_y);
+        match mem.lb(offsets::get().player_dir) {
             0x00 => y += 1, // Down
             0x04 => y -= 1, // Up
             0x0C => x += 1, // Right
@@ -28,7 +40,7 @@ pub fn sprite_check(cpu: &mut Cpu, mem: &mut Memory, interface_data: &mut Interf
             if player.movement_data.map_id == map_id && player.check_collision(x, y) {
                 // If there was a player set a sentinel value so the game thinks that there is
                 // something in the way.
-                mem.sb(offsets::SPRITE_INDEX, 0xFF);
+                mem.sb(offsets::get().sprite_index, 0xFF);
                 interface_data.sprite_id_state = DataState::Hacked;
                 interface_data.last_interaction = *id;
                 break;
@@ -39,44 +51,140 @@ pub fn sprite_check(cpu: &mut Cpu, mem: &mut Memory, interface_data: &mut Interf
 
 pub fn display_text(cpu: &mut Cpu, mem: &mut Memory, interface_data: &mut InterfaceData) {
     if interface_data.sprite_id_state == DataState::Hacked &&
-        cpu.pc == offsets::DISPLAY_TEXT_ID_AFTER_INIT
+        cpu.pc == offsets::get().display_text_id_after_init
     {
         // Skip unnecessary parts of the DISPLAY_TEXT_ID routine releated to finding the correct
         // message address when we are interacting with a hacked object.
-        cpu.jump(offsets::DISPLAY_TEXT_SETUP_DONE);
+        cpu.jump(offsets::get().display_text_setup_done);
         // Set the delay time (this is normally set in the middle of the code we just skipped)
-        mem.sb(offsets::FRAME_COUNTER, 30);
+        mem.sb(offsets::get().frame_counter, 30);
 
         interface_data.text_state = DataState::Hacked;
-        interface_data.create_message_box("PLAYER has nothing\nto say.");
 
-        interface_data.network_request = NetworkRequest::Battle(interface_data.last_interaction);
-        // We probably want to defer this until as late as possible, to avoid latency causing too
-        // much of an issue
-        interface_data.state = InterfaceState::Waiting;
+        // The message box is left open while the player chooses what to do, and for battles
+        // until the other player answers the challenge, at which point the result is added to
+        // the message.
+        let id = interface_data.last_interaction;
+        match interface_data.request.take() {
+            Some(request) if request.from == id => {
+                interface_data.open_dialog(Dialog::Request(id), &request.message);
+            }
+            request => {
+                interface_data.request = request;
+                let greeting = interface_data.greeting(id);
+                interface_data.open_dialog(Dialog::Interaction(id), &greeting);
+            }
+        }
     }
 
     // If the text state is hacked when running the text processor, read from our message buffer
     // instead of from the emulator's memory
     if interface_data.text_state == DataState::Hacked &&
-        (cpu.pc == offsets::GET_NEXT_CHAR_1 || cpu.pc == offsets::GET_NEXT_CHAR_2)
+        (cpu.pc == offsets::get().get_next_char_1 || cpu.pc == offsets::get().get_next_char_2)
     {
-        cpu.a = interface_data.current_message.pop_front().unwrap_or(text::special::TERMINATOR);
+        cpu.a = interface_data.next_char();
         cpu.pc += 1;
     }
 
     // Ensure that when we leave the text processor, we reset the text state so that the next call
     // to the text processor will correctly read from the game.
-    if cpu.pc == offsets::TEXT_PROCESSOR_END {
+    if cpu.pc == offsets::get().text_processor_end {
         interface_data.text_state = DataState::Normal;
     }
 }
 
+pub fn link_battle(cpu: &Cpu, mem: &mut Memory, interface_data: &mut InterfaceData) {
+    let battle = match interface_data.link_battle.as_mut() {
+        Some(battle) => battle,
+        None => return,
+    };
+
+    // The game clears the battle flag once the battle has finished. This needs to be handled
+    // straight away, so that the restored party is in place before the game checks if the player
+    // has blacked out.
+    if mem.lb(offsets::get().active_battle) == values::ActiveBattle::None as u8 {
+        super::end_battle(mem, interface_data);
+        return;
+    }
+
+    // The game keeps calling the exchange routine until it receives something from the other
+    // player, so it is enough to only fill in the data once the opponent's action has arrived.
+    if cpu.pc == offsets::get().serial_exchange_nybble &&
+        mem.lb(offsets::get().nybble_receive_data) == values::link_battle::NO_DATA
+    {
+        if !battle.action_sent {
+            let action = mem.lb(offsets::get().nybble_send_data);
+            interface_data.network_request = NetworkRequest::BattleAction(battle.opponent, action);
+            battle.action_sent = true;
+        }
+
+        if let Some(action) = battle.opponent_actions.pop_front() {
+            // Make it look like the action was received over the link cable
+            let data = values::link_battle::NYBBLE_PREAMBLE | action;
+            mem.sb(offsets::get().serial_receive_data, data);
+            battle.action_sent = false;
+        }
+    }
+}
+
+/// Emulate the link cable over the network, one byte (and one network round trip) per transfer.
+/// This is slow for whole parties, but the game may choose each byte from the last one received.
+pub fn link_cable(mem: &mut Memory, interface_data: &mut InterfaceData) {
+    // Battles started by talking to other players reuse the game's link battle code, but the
+    // link cable is not involved.
+    if interface_data.link_battle.is_some() {
+        return;
+    }
+
+    if let Some(cable) = interface_data.link_cable.as_mut() {
+        let link_state = mem.lb(offsets::get().link_state);
+        if link_state != values::LinkState::None as u8 {
+            cable.connected = true;
+        }
+
+        // Unplug the cable once the game has finished with it. This is either when the game has
+        // left the Cable Club, or when it gave up connecting and the player walked away.
+        let walked_away = !cable.connected && mem.lb(offsets::get().map_id) != cable.map_id;
+        if walked_away || (cable.connected && link_state == values::LinkState::None as u8) {
+            interface_data.link_cable = None;
+            interface_data.network_request = NetworkRequest::CloseLink;
+            return;
+        }
+    }
+
+    // Only the side driving the clock starts transfers, the other side is handled when its
+    // partner's data arrives.
+    let start = values::serial::START_TRANSFER | values::serial::INTERNAL_CLOCK;
+    if mem.lb(offsets::SERIAL_CONTROL) & start != start {
+        return;
+    }
+
+    match interface_data.link_cable.as_mut() {
+        None => {
+            interface_data.link_cable = Some(super::LinkCable {
+                partner: None,
+                map_id: mem.lb(offsets::get().map_id),
+                connected: false,
+                transfer_started: None,
+            });
+            interface_data.network_request = NetworkRequest::OpenLink;
+        }
+
+        Some(cable) => {
+            if let (Some(partner), None) = (cable.partner, cable.transfer_started) {
+                cable.transfer_started = Some(Instant::now());
+                let data = mem.lb(offsets::SERIAL_DATA);
+                interface_data.network_request = NetworkRequest::LinkTransfer(partner, data);
+            }
+        }
+    }
+}
+
 pub fn sprite_update_tracker(cpu: &Cpu, mem: &Memory, interface_data: &mut InterfaceData) {
-    if cpu.pc == offsets::UPDATE_SPRITES {
-        interface_data.sprites_enabled = mem.lb(offsets::SPRITES_ENABLED) == 0x01;
+    if cpu.pc == offsets::get().update_sprites {
+        interface_data.sprites_enabled = mem.lb(offsets::get().sprites_enabled) == 0x01;
     }
-    else if cpu.pc == offsets::CLEAR_SPRITES {
+    else if cpu.pc == offsets::get().clear_sprites {
         interface_data.sprites_enabled = false;
     }
 }
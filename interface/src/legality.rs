@@ -0,0 +1,370 @@
+//! Checking that a team could have been obtained by playing the game, so that players cannot
+//! battle with pokemon that they made by editing their game's memory.
+use std::collections::HashMap;
+
+use crate::{
+    data::{BaseStats, Learnset, NameTables, PokemonData, Stats, BATTLE_DATA_SIZE},
+    extract::{self, Rom},
+    offsets,
+    save::{self, PARTY_CAPACITY},
+    values::{moves, MAX_LEVEL, NUM_MOVES, NUM_SPECIES},
+};
+
+/// Why a pokemon could not have been obtained in the game
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Problem {
+    InvalidSpecies,
+    InvalidLevel(u8),
+    /// The species in the party's list of species is not the species of the pokemon
+    SpeciesMismatch(u8),
+    NoMoves,
+    UnknownMove(u8),
+    DuplicateMove(u8),
+    UnlearnableMove(u8),
+    WrongTypes,
+    /// The stats do not match what the game would calculate from the pokemon's DVs and stat
+    /// experience
+    WrongStats,
+    TooMuchHp,
+}
+
+/// The reason that a team was found to be illegal
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Illegal {
+    /// The battle data is the wrong size or does not contain a valid number of pokemon
+    InvalidData,
+    Pokemon { slot: usize, species: u8, problem: Problem },
+}
+
+/// Everything needed from the rom to check pokemon, read once when the server starts
+pub struct Legality {
+    names: NameTables,
+    /// The base stats of each species, indexed by the species id minus one
+    base_stats: Vec<BaseStats>,
+    /// The moves that each species can learn, including the moves that it could have learned
+    /// before it evolved. Indexed by the species id minus one.
+    learnsets: Vec<Learnset>,
+}
+
+impl Legality {
+    pub fn from_rom<R: Rom + ?Sized>(rom: &R) -> Legality {
+        let mut pre_evolutions = HashMap::new();
+        for species in 1..=NUM_SPECIES {
+            for evolution in extract::evolutions(rom, species) {
+                pre_evolutions.insert(evolution.species, species);
+            }
+        }
+
+        let learnsets = (1..=NUM_SPECIES)
+            .map(|species| {
+                let mut learnset = extract::learnset(rom, species);
+
+                // No species has more than two earlier stages, the limit stops a rom with a loop
+                // in its evolutions from hanging the server.
+                let mut current = species;
+                for _ in 0..2 {
+                    current = match pre_evolutions.get(&current) {
+                        Some(&pre_evolution) => pre_evolution,
+                        None => break,
+                    };
+                    let earlier = extract::learnset(rom, current);
+                    learnset.starting_moves.extend(earlier.starting_moves);
+                    learnset.level_moves.extend(earlier.level_moves);
+                    learnset.machine_moves.extend(earlier.machine_moves);
+                }
+                learnset
+            })
+            .collect();
+
+        let base_stats = (1..=NUM_SPECIES).map(|species| extract::base_stats(rom, species));
+        Legality { names: extract::name_tables(rom), base_stats: base_stats.collect(), learnsets }
+    }
+
+    pub fn names(&self) -> &NameTables {
+        &self.names
+    }
+
+    /// Check every pokemon in the battle data sent by a player before a battle
+    pub fn check_battle_data(&self, data: &[u8]) -> Result<(), Illegal> {
+        let count = data.first().copied().unwrap_or(0) as usize;
+        if data.len() != BATTLE_DATA_SIZE || count == 0 || count > PARTY_CAPACITY {
+            return Err(Illegal::InvalidData);
+        }
+
+        let start = offsets::get().player_battle_data_start;
+        let species_list = (offsets::get().party_species - start) as usize;
+        let party = (offsets::get().party_poke_1 - start) as usize;
+        let size = offsets::PARTY_POKE_SIZE as usize;
+
+        for slot in 0..count {
+            let pokemon = save::read_pokemon(&data[party + slot * size..], size);
+            let listed_species = data[species_list + slot];
+
+            let result = if listed_species == pokemon.species {
+                self.check_pokemon(&pokemon)
+            }
+            else {
+                Err(Problem::SpeciesMismatch(listed_species))
+            };
+            let species = pokemon.species;
+            result.map_err(|problem| Illegal::Pokemon { slot, species, problem })?;
+        }
+
+        Ok(())
+    }
+
+    /// Check that a single pokemon could have been obtained in the game
+    pub fn check_pokemon(&self, pokemon: &PokemonData) -> Result<(), Problem> {
+        // Some species ids are not used by the game, these have no pokedex number
+        let index = match self.names.species(pokemon.species) {
+            Some(species) if species.dex_number != 0 => species.id as usize - 1,
+            _ => return Err(Problem::InvalidSpecies),
+        };
+        if pokemon.level == 0 || pokemon.level > MAX_LEVEL {
+            return Err(Problem::InvalidLevel(pokemon.level));
+        }
+
+        let (a, b, c, d) = pokemon.moves;
+        let known_moves: Vec<_> =
+            [a, b, c, d].into_iter().filter(|&id| id != moves::NONE).collect();
+        if known_moves.is_empty() {
+            return Err(Problem::NoMoves);
+        }
+        for (i, &move_id) in known_moves.iter().enumerate() {
+            if move_id > NUM_MOVES {
+                return Err(Problem::UnknownMove(move_id));
+            }
+            if known_moves[..i].contains(&move_id) {
+                return Err(Problem::DuplicateMove(move_id));
+            }
+            if !self.learnsets[index].can_learn(move_id, pokemon.level) {
+                return Err(Problem::UnlearnableMove(move_id));
+            }
+        }
+
+        let base = &self.base_stats[index];
+        if (pokemon.type1, pokemon.type2) != (base.type1, base.type2) {
+            return Err(Problem::WrongTypes);
+        }
+
+        // The game only recalculates stats when a pokemon levels up or is taken out of the PC, so
+        // stat experience gained since then is not counted yet.
+        let mut without_stat_exp = pokemon.clone();
+        without_stat_exp.hp_ev = 0;
+        without_stat_exp.attack_ev = 0;
+        without_stat_exp.defense_ev = 0;
+        without_stat_exp.speed_ev = 0;
+        without_stat_exp.special_ev = 0;
+        let lowest = without_stat_exp.calculate_stats(base);
+        let highest = pokemon.calculate_stats(base);
+        if !stats_between(pokemon.stats(), lowest, highest) {
+            return Err(Problem::WrongStats);
+        }
+        if pokemon.hp > pokemon.max_hp {
+            return Err(Problem::TooMuchHp);
+        }
+
+        Ok(())
+    }
+
+    /// Explain why a team is illegal, using the names of the species and moves involved
+    pub fn describe(&self, illegal: &Illegal) -> String {
+        let (slot, species, problem) = match *illegal {
+            Illegal::InvalidData => return "The party data is invalid.".into(),
+            Illegal::Pokemon { slot, species, problem } => (slot, species, problem),
+        };
+
+        let pokemon = format!("{} in slot {}", self.names.species_name(species), slot + 1);
+        match problem {
+            Problem::InvalidSpecies => format!("The pokemon in slot {} does not exist.", slot + 1),
+            Problem::InvalidLevel(level) => format!("{} has an invalid level: {}.", pokemon, level),
+            Problem::SpeciesMismatch(listed) => format!(
+                "{} is listed in the party as {}.",
+                pokemon,
+                self.names.species_name(listed)
+            ),
+            Problem::NoMoves => format!("{} does not know any moves.", pokemon),
+            Problem::UnknownMove(id) => format!("{} knows an invalid move: {}.", pokemon, id),
+            Problem::DuplicateMove(id) => {
+                format!("{} knows {} twice.", pokemon, self.names.move_name(id))
+            }
+            Problem::UnlearnableMove(id) => {
+                format!("{} cannot learn {}.", pokemon, self.names.move_name(id))
+            }
+            Problem::WrongTypes => format!("{} has the wrong types.", pokemon),
+            Problem::WrongStats => format!("{} has impossible stats.", pokemon),
+            Problem::TooMuchHp => format!("{} has more HP than its maximum.", pokemon),
+        }
+    }
+}
+
+/// Whether every stat is between the stats in `lowest` and `highest`
+fn stats_between(stats: Stats, lowest: Stats, highest: Stats) -> bool {
+    let as_array =
+        |stats: Stats| [stats.max_hp, stats.attack, stats.defense, stats.speed, stats.special];
+    let (stats, lowest, highest) = (as_array(stats), as_array(lowest), as_array(highest));
+    (0..stats.len()).all(|i| lowest[i] <= stats[i] && stats[i] <= highest[i])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        test_rom::{TestRom, GROWL, PIKACHU, QUICK_ATTACK, RAICHU, THUNDERSHOCK},
+        values::types,
+    };
+
+    /// Pikachu, which learns Quick Attack at level 16 and evolves into Raichu
+    fn legality() -> Legality {
+        let mut rom = TestRom::new();
+        rom.add_pikachu();
+        rom.add_raichu();
+        Legality::from_rom(&rom)
+    }
+
+    /// A pokemon with its stats calculated from its DVs and stat experience
+    fn pokemon(legality: &Legality, species: u8, level: u8, moves: [u8; 4]) -> PokemonData {
+        let mut pokemon = PokemonData::test_data();
+        pokemon.species = species;
+        pokemon.level = level;
+        pokemon.moves = (moves[0], moves[1], moves[2], moves[3]);
+        pokemon.type1 = types::ELECTRIC;
+        pokemon.type2 = types::ELECTRIC;
+        pokemon.individual_values = (0x8D, 0x59);
+        pokemon.attack_ev = 500;
+        pokemon.recalculate_stats(&legality.base_stats[species as usize - 1]);
+        pokemon.hp = pokemon.max_hp;
+        pokemon
+    }
+
+    /// Battle data holding `party`, listed in the party as `species_list`
+    fn battle_data(party: &[PokemonData], species_list: &[u8]) -> Vec<u8> {
+        let start = offsets::get().player_battle_data_start;
+        let species_offset = (offsets::get().party_species - start) as usize;
+        let party_offset = (offsets::get().party_poke_1 - start) as usize;
+        let size = offsets::PARTY_POKE_SIZE as usize;
+
+        let mut data = vec![0; BATTLE_DATA_SIZE];
+        data[0] = party.len() as u8;
+        data[species_offset..species_offset + species_list.len()].copy_from_slice(species_list);
+        for (slot, pokemon) in party.iter().enumerate() {
+            save::write_pokemon(&mut data[party_offset + slot * size..], pokemon, size);
+        }
+        data
+    }
+
+    #[test]
+    fn legal_pokemon() {
+        let legality = legality();
+        let pikachu = pokemon(&legality, PIKACHU, 20, [THUNDERSHOCK, QUICK_ATTACK, 0, 0]);
+        assert_eq!(legality.check_pokemon(&pikachu), Ok(()));
+
+        // Stat experience gained since the stats were last calculated is not counted yet
+        let mut pikachu = pikachu;
+        pikachu.speed_ev = 10000;
+        assert_eq!(legality.check_pokemon(&pikachu), Ok(()));
+    }
+
+    #[test]
+    fn moves_learned_before_evolving() {
+        let legality = legality();
+        let raichu = pokemon(&legality, RAICHU, 30, [THUNDERSHOCK, QUICK_ATTACK, 0, 0]);
+        assert_eq!(legality.check_pokemon(&raichu), Ok(()));
+    }
+
+    #[test]
+    fn unlearnable_moves() {
+        let legality = legality();
+        let pikachu = pokemon(&legality, PIKACHU, 20, [THUNDERSHOCK, moves::POUND, 0, 0]);
+        let expected = Err(Problem::UnlearnableMove(moves::POUND));
+        assert_eq!(legality.check_pokemon(&pikachu), expected);
+
+        // Quick Attack is learned at level 16
+        let pikachu = pokemon(&legality, PIKACHU, 15, [QUICK_ATTACK, 0, 0, 0]);
+        let expected = Err(Problem::UnlearnableMove(QUICK_ATTACK));
+        assert_eq!(legality.check_pokemon(&pikachu), expected);
+
+        let pikachu = pokemon(&legality, PIKACHU, 20, [GROWL, 0, GROWL, 0]);
+        assert_eq!(legality.check_pokemon(&pikachu), Err(Problem::DuplicateMove(GROWL)));
+
+        let pikachu = pokemon(&legality, PIKACHU, 20, [0, 0, 0, 0]);
+        assert_eq!(legality.check_pokemon(&pikachu), Err(Problem::NoMoves));
+    }
+
+    #[test]
+    fn wrong_stats() {
+        let legality = legality();
+        let pikachu = pokemon(&legality, PIKACHU, 20, [THUNDERSHOCK, 0, 0, 0]);
+
+        let mut edited = pikachu.clone();
+        edited.attack += 1;
+        assert_eq!(legality.check_pokemon(&edited), Err(Problem::WrongStats));
+
+        // Changing the DVs without changing the stats
+        let mut edited = pikachu.clone();
+        edited.individual_values = (0xFF, 0xFF);
+        assert_eq!(legality.check_pokemon(&edited), Err(Problem::WrongStats));
+
+        let mut edited = pikachu.clone();
+        edited.hp = edited.max_hp + 1;
+        assert_eq!(legality.check_pokemon(&edited), Err(Problem::TooMuchHp));
+
+        let mut edited = pikachu;
+        edited.type2 = types::FLYING;
+        assert_eq!(legality.check_pokemon(&edited), Err(Problem::WrongTypes));
+    }
+
+    #[test]
+    fn invalid_species_and_level() {
+        let legality = legality();
+        // Species ids that are not in the pokedex
+        let missingno = pokemon(&legality, 0x1F, 20, [THUNDERSHOCK, 0, 0, 0]);
+        assert_eq!(legality.check_pokemon(&missingno), Err(Problem::InvalidSpecies));
+
+        let mut pikachu = pokemon(&legality, PIKACHU, 20, [THUNDERSHOCK, 0, 0, 0]);
+        pikachu.level = MAX_LEVEL + 1;
+        let expected = Err(Problem::InvalidLevel(MAX_LEVEL + 1));
+        assert_eq!(legality.check_pokemon(&pikachu), expected);
+    }
+
+    #[test]
+    fn check_battle_data() {
+        let legality = legality();
+        let pikachu = pokemon(&legality, PIKACHU, 20, [THUNDERSHOCK, 0, 0, 0]);
+        let raichu = pokemon(&legality, RAICHU, 30, [THUNDERSHOCK, QUICK_ATTACK, 0, 0]);
+        let party = [pikachu.clone(), raichu];
+
+        let data = battle_data(&party, &[PIKACHU, RAICHU]);
+        assert_eq!(legality.check_battle_data(&data), Ok(()));
+
+        // The list of species has to agree with the pokemon
+        let data = battle_data(&party, &[PIKACHU, PIKACHU]);
+        let problem = Problem::SpeciesMismatch(PIKACHU);
+        let expected = Illegal::Pokemon { slot: 1, species: RAICHU, problem };
+        assert_eq!(legality.check_battle_data(&data), Err(expected));
+
+        let mut edited = pikachu;
+        edited.attack += 1;
+        let data = battle_data(&[edited], &[PIKACHU]);
+        let expected = Illegal::Pokemon { slot: 0, species: PIKACHU, problem: Problem::WrongStats };
+        assert_eq!(legality.check_battle_data(&data), Err(expected));
+    }
+
+    #[test]
+    fn invalid_battle_data() {
+        let legality = legality();
+        let pikachu = pokemon(&legality, PIKACHU, 20, [THUNDERSHOCK, 0, 0, 0]);
+        let data = battle_data(&[pikachu], &[PIKACHU]);
+
+        assert_eq!(legality.check_battle_data(&data[..100]), Err(Illegal::InvalidData));
+        assert_eq!(legality.check_battle_data(&[]), Err(Illegal::InvalidData));
+
+        let mut empty = data.clone();
+        empty[0] = 0;
+        assert_eq!(legality.check_battle_data(&empty), Err(Illegal::InvalidData));
+
+        let mut too_many = data;
+        too_many[0] = PARTY_CAPACITY as u8 + 1;
+        assert_eq!(legality.check_battle_data(&too_many), Err(Illegal::InvalidData));
+    }
+}
@@ -1,14 +1,23 @@
 //! Crate for interfacing with the emulator
-use std::collections::{HashMap, VecDeque};
+use std::{
+    collections::{HashMap, VecDeque},
+    time::{Duration, Instant},
+};
 
-use gb_emu::{graphics, mmu::Memory};
+use gb_emu::{graphics, joypad, mmu::Memory};
 
 pub mod data;
 pub mod extract;
 pub mod hacks;
+pub mod legality;
 pub mod offsets;
+pub mod save;
+pub mod symbols;
 pub mod text;
+#[cfg(test)]
+mod test_rom;
 pub mod values;
+pub mod write;
 
 #[derive(PartialEq)]
 enum DataState {
@@ -24,10 +33,85 @@ pub enum InterfaceState {
 
 pub type PlayerId = u32;
 
+/// Why the game's message box has been left open for another player
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Dialog {
+    /// We talked to the player, and need to choose what to do with them
+    Interaction(PlayerId),
+    /// The player sent us a request (such as a battle challenge) that needs an answer
+    Request(PlayerId),
+}
+
+/// A request from another player that is waiting to be shown in the game's message box
+struct PendingRequest {
+    from: PlayerId,
+    message: String,
+    /// The number of overworld loops until the A button is pressed again, in case the last press
+    /// was used up by something else in front of the player
+    retry: u32,
+}
+
+/// How many overworld loops the A button is held for to open the message box for a request
+const REQUEST_PRESS_LOOPS: u32 = 4;
+
+/// How many overworld loops to wait before trying to open the message box for a request again
+const REQUEST_RETRY_LOOPS: u32 = 120;
+
 #[derive(PartialEq)]
 pub enum NetworkRequest {
     None,
+    /// Challenge a player that we talked to to a battle
     Battle(PlayerId),
+    /// Start a trade with a player that we talked to
+    Trade(PlayerId),
+    /// Look at the party of a player that we talked to
+    ViewParty(PlayerId),
+    /// Start writing a chat message to a player that we talked to
+    Chat(PlayerId),
+    AcceptBattle(PlayerId),
+    DeclineBattle(PlayerId),
+    /// Send the action we chose this turn to the player we are battling
+    BattleAction(PlayerId, u8),
+    /// Offer the pokemon in a party slot to a player for a trade
+    TradeOffer(PlayerId, u8),
+    ConfirmTrade(PlayerId),
+    CancelTrade(PlayerId),
+    /// Ask the server to connect our link cable to another player
+    OpenLink,
+    CloseLink,
+    /// Send a byte over the link cable, driving the clock
+    LinkTransfer(PlayerId, u8),
+}
+
+/// How long to wait for the other side of a link cable transfer before acting as if the cable had
+/// been unplugged
+const LINK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// An emulated link cable, connected to another player through the server. The side that drives
+/// the clock sends its byte to the other player, who sends back the byte that it had ready.
+pub struct LinkCable {
+    /// The player on the other end of the cable, or `None` while waiting for the server to find one
+    pub partner: Option<PlayerId>,
+    /// The map that the cable was connected on
+    map_id: u8,
+    /// Whether the game has finished connecting to the other player
+    connected: bool,
+    /// When we started the transfer that we are waiting for a reply to
+    transfer_started: Option<Instant>,
+}
+
+/// A battle against another player. Both players' games run the battle as a link battle, with the
+/// actions that would normally be sent over the link cable sent over the network instead.
+pub struct LinkBattle {
+    pub opponent: PlayerId,
+    /// Actions chosen by the opponent that the game has not read yet
+    opponent_actions: VecDeque<u8>,
+    /// Whether our action for the current exchange has been sent to the opponent
+    action_sent: bool,
+    /// Our party before the battle, which is restored after the battle so that link battles do
+    /// not affect the player's game
+    saved_party: data::BattleData,
+    saved_clock: u8,
 }
 
 pub struct InterfaceData {
@@ -35,9 +119,20 @@ pub struct InterfaceData {
     pub network_request: NetworkRequest,
     pub players: HashMap<u32, data::PlayerData>,
     pub last_interaction: u32,
+    /// A message box that the game has just finished writing and is now waiting on, the client
+    /// shows the choices for it
+    pub new_dialog: Option<Dialog>,
+    pub allow_battles: bool,
+    pub link_battle: Option<LinkBattle>,
+    pub link_cable: Option<LinkCable>,
     sprite_id_state: DataState,
     text_state: DataState,
     current_message: VecDeque<u8>,
+    /// The message box that has been opened without being closed
+    open_dialog: Option<Dialog>,
+    request: Option<PendingRequest>,
+    /// The number of overworld loops left that the A button is held for
+    request_press: u32,
     sprites_enabled: bool,
 }
 
@@ -48,9 +143,16 @@ impl InterfaceData {
             network_request: NetworkRequest::None,
             players: HashMap::new(),
             last_interaction: 0,
+            new_dialog: None,
+            allow_battles: true,
+            link_battle: None,
+            link_cable: None,
             sprite_id_state: DataState::Normal,
             text_state: DataState::Normal,
             current_message: VecDeque::new(),
+            open_dialog: None,
+            request: None,
+            request_press: 0,
             sprites_enabled: false,
         }
     }
@@ -59,19 +161,246 @@ impl InterfaceData {
         self.sprites_enabled
     }
 
+    /// The message shown when talking to a player
+    pub fn greeting(&self, id: PlayerId) -> String {
+        match self.players.get(&id) {
+            Some(player) => player.greeting_message(),
+            None => data::DEFAULT_GREETING.replace(data::GREETING_NAME, data::UNKNOWN_PLAYER_NAME),
+        }
+    }
+
     pub fn create_message_box(&mut self, input: &str) {
+        self.open_message_box(input);
+        self.close_message_box();
+    }
+
+    /// Start a message box without ending it, so that more text can be added to it later. This
+    /// must only be used while the game is waiting, otherwise the game will reach the end of the
+    /// text before the rest has been added.
+    pub fn open_message_box(&mut self, input: &str) {
         self.current_message.push_back(text::special::TEXT_START);
-        self.current_message.extend(text::Encoder::new(input));
+        self.current_message.extend(text::layout(input));
+    }
+
+    /// Open a message box for another player, which is left open after the game has written
+    /// `input` until the dialog is ended with `end_dialog`
+    fn open_dialog(&mut self, dialog: Dialog, input: &str) {
+        self.open_message_box(input);
+        self.open_dialog = Some(dialog);
+    }
+
+    /// The message box that the game is waiting on, if any
+    pub fn dialog(&self) -> Option<Dialog> {
+        match self.state {
+            InterfaceState::Waiting => self.open_dialog,
+            InterfaceState::Normal => None,
+        }
+    }
+
+    /// Finish the message box opened for another player, adding `message` to the end of it, and
+    /// let the game continue. Does nothing if the message box has already been finished.
+    pub fn end_dialog(&mut self, message: Option<&str>) {
+        if self.open_dialog.take().is_none() {
+            return;
+        }
+        if let Some(message) = message {
+            self.append_message(message);
+        }
+        self.close_message_box();
+        self.new_dialog = None;
+        self.state = InterfaceState::Normal;
+    }
+
+    /// The next character of our message for the game's text processor. Once the game has been
+    /// given everything in a message box that has been left open, the game waits for the rest.
+    fn next_char(&mut self) -> u8 {
+        let next = self.current_message.pop_front();
+        if next.is_some() && self.current_message.is_empty() && self.open_dialog.is_some() {
+            self.new_dialog = self.open_dialog;
+            self.state = InterfaceState::Waiting;
+        }
+        next.unwrap_or(text::special::TERMINATOR)
+    }
+
+    /// Show a request from another player in the game's message box the next time the player is
+    /// free. Returns false if another request is already waiting to be shown.
+    pub fn queue_request(&mut self, from: PlayerId, message: String) -> bool {
+        if self.request.is_some() || self.open_dialog.is_some() {
+            return false;
+        }
+        self.request = Some(PendingRequest { from, message, retry: 0 });
+        true
+    }
+
+    /// Remove a request that has not been shown yet, returns false if there was no request from
+    /// `from` waiting to be shown
+    pub fn withdraw_request(&mut self, from: PlayerId) -> bool {
+        match self.request.as_ref() {
+            Some(request) if request.from == from => {
+                self.request = None;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Forget any request waiting to be shown
+    pub fn clear_request(&mut self) {
+        self.request = None;
+    }
+
+    /// Whether a request from another player is waiting to be shown
+    pub fn has_request(&self) -> bool {
+        self.request.is_some()
+    }
+
+    /// Called each time the game reaches the start of the overworld loop. The game only opens a
+    /// message box when the player presses A, so a request is shown by pressing A for them. The
+    /// sprite check hack then makes the game think that the player talked to the sender.
+    fn press_for_request(&mut self, mem: &mut Memory) {
+        if self.request_press > 0 {
+            self.request_press -= 1;
+            if self.request_press == 0 {
+                mem.joypad.a = joypad::State::Released;
+            }
+        }
+
+        let request = match self.request.as_mut() {
+            Some(request) => request,
+            None => return,
+        };
+        if request.retry > 0 {
+            request.retry -= 1;
+            return;
+        }
+        request.retry = REQUEST_RETRY_LOOPS;
+        self.request_press = REQUEST_PRESS_LOOPS;
+        mem.joypad.a = joypad::State::Pressed;
+    }
+
+    /// Whether the player is facing a request that we pressed A to open
+    fn request_pressed(&self) -> Option<PlayerId> {
+        match self.request.as_ref() {
+            // The game may only see the press a loop after it was released
+            Some(request) if request.retry + REQUEST_PRESS_LOOPS + 1 >= REQUEST_RETRY_LOOPS => {
+                Some(request.from)
+            }
+            _ => None,
+        }
+    }
+
+    /// Add a new paragraph to a message box started by `open_message_box`
+    pub fn append_message(&mut self, input: &str) {
+        self.current_message.push_back(text::special::PARAGRAPH);
+        self.current_message.extend(text::layout(input));
+    }
+
+    /// End a message box started by `open_message_box`
+    pub fn close_message_box(&mut self) {
         self.current_message.push_back(text::special::END_MSG);
         self.current_message.push_back(text::special::TERMINATOR);
     }
+
+    /// Queue an action chosen by the player we are battling
+    pub fn receive_battle_action(&mut self, from: PlayerId, action: u8) {
+        match self.link_battle.as_mut() {
+            Some(battle) if battle.opponent == from => battle.opponent_actions.push_back(action),
+            _ => {}
+        }
+    }
+
+    /// End the battle against a player that is no longer connected, by acting as if they ran away
+    pub fn forfeit_link_battle(&mut self, opponent: PlayerId) {
+        self.receive_battle_action(opponent, values::link_battle::RUN);
+    }
+
+    /// Check if the game is waiting for the other side of a link cable transfer. The game must not
+    /// run while it is waiting, otherwise it can give up on the other player before they answer.
+    pub fn link_waiting(&mut self, mem: &mut Memory) -> bool {
+        let cable = match self.link_cable.as_mut() {
+            Some(cable) => cable,
+            None => return false,
+        };
+
+        match cable.transfer_started {
+            Some(started) if started.elapsed() > LINK_TIMEOUT => {
+                cable.transfer_started = None;
+                finish_serial_transfer(mem, values::serial::NO_DATA);
+                false
+            }
+            Some(_) => true,
+            None => false,
+        }
+    }
+
+    /// The server has found a player for the other end of our link cable
+    pub fn link_opened(&mut self, partner: PlayerId) {
+        if let Some(cable) = self.link_cable.as_mut() {
+            cable.partner = Some(partner);
+        }
+    }
+
+    /// Handle a transfer started by the other player, returning the byte that we send back
+    pub fn link_transfer(&mut self, mem: &mut Memory, from: PlayerId, data: u8) -> u8 {
+        match self.link_cable.as_ref() {
+            Some(cable) if cable.partner == Some(from) => {}
+            _ => return values::serial::NO_DATA,
+        }
+
+        // The data is only exchanged if the game is ready for the other player to drive the clock
+        let control = mem.lb(offsets::SERIAL_CONTROL);
+        let ready = values::serial::START_TRANSFER;
+        if control & (values::serial::START_TRANSFER | values::serial::INTERNAL_CLOCK) != ready {
+            return values::serial::NO_DATA;
+        }
+
+        let reply = mem.lb(offsets::SERIAL_DATA);
+        finish_serial_transfer(mem, data);
+        reply
+    }
+
+    /// Finish the transfer that we started, with the byte sent back by the other player
+    pub fn link_reply(&mut self, mem: &mut Memory, from: PlayerId, data: u8) {
+        match self.link_cable.as_mut() {
+            Some(cable) if cable.partner == Some(from) && cable.transfer_started.is_some() => {
+                cable.transfer_started = None;
+                finish_serial_transfer(mem, data);
+            }
+            _ => {}
+        }
+    }
+
+    /// Unplug the link cable if `player` is on the other end of it
+    pub fn link_closed(&mut self, mem: &mut Memory, player: PlayerId) {
+        let cable = match self.link_cable.take() {
+            Some(cable) if cable.partner == Some(player) => cable,
+            other => {
+                self.link_cable = other;
+                return;
+            }
+        };
+
+        if cable.transfer_started.is_some() {
+            finish_serial_transfer(mem, values::serial::NO_DATA);
+        }
+    }
+}
+
+/// Finish a serial transfer in the same way as the hardware: by replacing the data register with
+/// the byte that was received and raising the serial interrupt.
+fn finish_serial_transfer(mem: &mut Memory, data: u8) {
+    mem.sb(offsets::SERIAL_DATA, data);
+    let control = mem.lb(offsets::SERIAL_CONTROL);
+    mem.sb(offsets::SERIAL_CONTROL, control & !values::serial::START_TRANSFER);
+    let flags = mem.lb(offsets::INTERRUPT_FLAGS);
+    mem.sb(offsets::INTERRUPT_FLAGS, flags | values::serial::INTERRUPT);
 }
 
 pub fn get_tile_id_addr(x: u8, y: u8) -> u16 {
     let y_offset = (((y + 4) & 0xF0) >> 3) as u16;
     let x_offset = ((x >> 3) + 0x14) as u16;
 
-    offsets::TILE_MAP + 20 * y_offset + x_offset
+    offsets::get().tile_map + 20 * y_offset + x_offset
 }
 
 /// Loads a target party into the OAK trainer data slot.
@@ -79,8 +408,8 @@ pub fn load_trainer_party(party: data::Party, mem: &mut Memory) {
     let pokemon = party.pokemon;
     let pokemon_array = [pokemon.0, pokemon.1, pokemon.2, pokemon.3, pokemon.4, pokemon.5];
 
-    let mut addr = (offsets::PROF_OAK_DATA_ADDR & 0x3FFF) as usize;
-    let bank = offsets::PROF_OAK_DATA_BANK;
+    let mut addr = (offsets::get().prof_oak_data_addr & 0x3FFF) as usize;
+    let bank = offsets::get().prof_oak_data_bank;
 
     mem.cart.rom[bank][addr] = 0xFF;
     addr += 1;
@@ -92,26 +421,106 @@ pub fn load_trainer_party(party: data::Party, mem: &mut Memory) {
     mem.cart.rom[bank][addr] = 0;
 }
 
-pub fn set_battle(mem: &mut Memory, enemy: &data::PlayerData, battle_data: data::BattleData) {
-    mem.sb(offsets::BATTLE_TYPE, values::BattleType::Normal as u8);
-    mem.sb(offsets::ACTIVE_BATTLE, values::ActiveBattle::Trainer as u8);
-    mem.sb(offsets::IS_LINK_BATTLE, values::TRUE);
-    mem.sb(offsets::CURRRENT_OPPONENT, values::TrainerClass::ProfOak as u8 + values::TRAINER_TAG);
+/// Start a link battle against another player. Both players must call this with the same random
+/// numbers, and exactly one of them must be the `leader`.
+pub fn set_battle(
+    mem: &mut Memory,
+    interface_data: &mut InterfaceData,
+    opponent: PlayerId,
+    battle_data: data::BattleData,
+    random_numbers: &[u8],
+    leader: bool,
+) {
+    // The opponent's details may not have arrived yet (or they may have already left), but the
+    // battle still has to start so that both games stay in sync.
+    let enemy_name = match interface_data.players.get(&opponent) {
+        Some(enemy) => enemy.name.clone(),
+        None => text::Encoder::new(data::UNKNOWN_PLAYER_NAME).collect(),
+    };
+
+    interface_data.link_battle = Some(LinkBattle {
+        opponent,
+        opponent_actions: VecDeque::new(),
+        action_sent: false,
+        saved_party: extract::battle_data(mem),
+        saved_clock: mem.lb(offsets::get().serial_connection_status),
+    });
+
+    mem.sb(offsets::get().battle_type, values::BattleType::Normal as u8);
+    mem.sb(offsets::get().active_battle, values::ActiveBattle::Trainer as u8);
+    mem.sb(offsets::get().link_state, values::LinkState::Battling as u8);
+    let opponent = values::TrainerClass::ProfOak as u8 + values::TRAINER_TAG;
+    mem.sb(offsets::get().currrent_opponent, opponent);
 
-    let mut offset = offsets::ENEMY_BATTLE_DATA_START;
+    let clock = if leader { values::SerialClock::Internal } else { values::SerialClock::External };
+    mem.sb(offsets::get().serial_connection_status, clock as u8);
+
+    // The game uses a list of random numbers shared between both players for link battles, so that
+    // both games get the same results.
+    mem.sb(offsets::get().link_random_number_index, 0);
+    let random_numbers = random_numbers.iter().take(values::link_battle::NUM_RANDOM_NUMBERS);
+    for (i, &val) in random_numbers.enumerate() {
+        mem.sb(offsets::get().link_random_numbers + i as u16, val);
+    }
+
+    let mut offset = offsets::get().enemy_battle_data_start;
     for val in battle_data {
         mem.sb(offset, val);
         offset += 1;
     }
 
-    offset = offsets::ENEMY_NAME_START;
-    for &val in &enemy.name {
+    offset = offsets::get().enemy_name_start;
+    for &val in &enemy_name {
         mem.sb(offset, val);
         offset += 1;
     }
     mem.sb(offset, text::special::TERMINATOR);
 }
 
+/// Restore the state that was changed by `set_battle` once the battle has finished
+pub fn end_battle(mem: &mut Memory, interface_data: &mut InterfaceData) {
+    let battle = match interface_data.link_battle.take() {
+        Some(battle) => battle,
+        None => return,
+    };
+
+    let mut offset = offsets::get().player_battle_data_start;
+    for val in battle.saved_party {
+        mem.sb(offset, val);
+        offset += 1;
+    }
+
+    mem.sb(offsets::get().link_state, values::LinkState::None as u8);
+    mem.sb(offsets::get().serial_connection_status, battle.saved_clock);
+}
+
+/// Put a pokemon received in a trade into a party slot, evolving it first if its species evolves
+/// when traded.
+pub fn receive_trade(mem: &mut Memory, slot: u8, mut record: data::PokemonRecord) {
+    let species = record.data.species;
+    if let Some(evolution) = extract::trade_evolution(mem, species) {
+        // Pokemon without a nickname are renamed to the species they evolved into
+        if record.nickname == extract::species_name(mem, species) {
+            record.nickname = extract::species_name(mem, evolution);
+        }
+
+        let base = extract::base_stats(mem, evolution);
+        record.data.species = evolution;
+        record.data.type1 = base.type1;
+        record.data.type2 = base.type2;
+        record.data.recalculate_stats(&base);
+    }
+
+    // Like in the game, receiving a pokemon registers it in the pokedex
+    let dex_index = extract::pokedex_number(mem, record.data.species).saturating_sub(1) as u16;
+    for flags in [offsets::get().pokedex_owned, offsets::get().pokedex_seen] {
+        let addr = flags + dex_index / 8;
+        mem.sb(addr, mem.lb(addr) | 1 << (dex_index % 8));
+    }
+
+    write::party_pokemon(mem, slot, &record);
+}
+
 /// Render a 16x16 sprite
 /// Returns true if the sprite was drawn to the screen
 pub fn render_sprite(mem: &mut Memory, spritesheet: &[u8], sprite_data: &data::SpriteData) -> bool {
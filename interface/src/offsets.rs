@@ -1,90 +1,408 @@
-// Player positional data
-pub const MAP_ID: u16 = 0xD35E;
-pub const MAP_Y: u16 = 0xD361;
-pub const MAP_X: u16 = 0xD362;
-pub const PLAYER_DY: u16 = 0xC103;
-pub const PLAYER_DX: u16 = 0xC105;
-
-// The direction which the player is facing (0: down, 4: up, 8: left, 12: right)
-pub const PLAYER_DIR: u16 = 0xC109;
-
-// When a player moves, this value counts down from 8 to 0
-pub const WALK_COUNTER: u16 = 0xCFC5;
-
-// General player data
-pub const PLAYER_NAME_START: u16 = 0xD158;
-
-// The address of the player spritesheet encoded as 2bpp in the rom
-pub const RED_SPRITE_ADDR: u16 = 0x4180;
-pub const RED_SPRITE_BANK: usize = 5;
-pub const BLUE_SPRITE_ADDR: u16 = 0x4300;
-pub const BLUE_SPRITE_BANK: usize = 5;
-pub const OAK_SPRITE_ADDR: u16 = 0x4480;
-pub const OAK_SPRITE_BANK: usize = 5;
-
-// The address of the main font encoded as a 1bpp sprite in the rom
-pub const FONT_ADDR: u16 = 0x5A80;
-pub const FONT_BANK: usize = 4;
-
-// The address of the textbox border encoded as 2bpp sprite in the rom
-pub const BORDER_ADDR: u16 = 0x6288 + 2 * 8 * (4 * 6 + 1);
-pub const BORDER_BANK: usize = 4;
-
-// The location of the tile map
-pub const TILE_MAP: u16 = 0xC3A0;
-
-// Useful addresses for hacks
-pub const LOADED_ROM_BANK: u16 = 0xFFB8;
-pub const FRAME_COUNTER: u16 = 0xFFD5;
-pub const BANK_SWITCH: u16 = 0x35D6;
-
-// Addresses for sprite check hack
-pub const NUM_SPRITES: u16 = 0xD4E1;
-pub const OVERWORLD_LOOP_START: u16 = 0x03FF;
-pub const SPRITE_CHECK_START: u16 = 0x0B23;
-pub const SPRITE_CHECK_EXIT_1: u16 = 0x0BA0;
-pub const SPRITE_CHECK_EXIT_2: u16 = 0x0BC4;
-pub const SPRITE_INDEX: u16 = 0xFF8C;
-
-// Addresses for sprite update hack
-pub const CLEAR_SPRITES: u16 = 0x0082;
-pub const UPDATE_SPRITES: u16 = 0x2429;
-pub const SPRITES_ENABLED: u16 = 0xCFCB;
-
-// Addresses for display text hack
-pub const DISPLAY_TEXT_ID: u16 = 0x2920;
-pub const DISPLAY_TEXT_ID_AFTER_INIT: u16 = 0x292B;
-pub const DISPLAY_TEXT_SETUP_DONE: u16 = 0x29CD;
-pub const GET_NEXT_CHAR_1: u16 = 0x1B55;
-pub const GET_NEXT_CHAR_2: u16 = 0x1956;
-pub const TEXT_PROCESSOR_END: u16 = 0x1B5E;
-
-// Addresses for battle hack
-pub const TRAINER_CLASS: u16 = 0xD031;
-pub const TRAINER_NAME: u16 = 0xD04A;
-pub const TRAINER_NUM: u16 = 0xD05D;
-pub const ACTIVE_BATTLE: u16 = 0xD057;
-pub const CURRRENT_OPPONENT: u16 = 0xD059;
-pub const CURRENT_ENEMY_LEVEL: u16 = 0xD127;
-pub const CURRENT_ENEMY_NICK: u16 = 0x0000;
-pub const BATTLE_TYPE: u16 = 0xD05A;
-pub const IS_LINK_BATTLE: u16 = 0xD12B;
-
-// The Prof. Oak battle is unused by the game, so it is a convenient place to replace with our
-// battle data.
-pub const PROF_OAK_DATA_ADDR: u16 = 0x621D;
-pub const PROF_OAK_DATA_BANK: usize = 0xE;
-
-// Addresses for battle data
-pub const PLAYER_BATTLE_DATA_START: u16 = 0xD163;
-pub const ENEMY_BATTLE_DATA_START: u16 = 0xD89C;
-pub const ENEMY_NAME_START: u16 = 0xD887;
-
-// Addresses for specific party data
-pub const PARTY_COUNT: u16 = 0xD163;
-pub const PARTY_POKE_1: u16 = 0xD16B;
-pub const PARTY_POKE_2: u16 = 0xD197;
-pub const PARTY_POKE_3: u16 = 0xD1C3;
-pub const PARTY_POKE_4: u16 = 0xD1EF;
-pub const PARTY_POKE_5: u16 = 0xD21B;
-pub const PARTY_POKE_6: u16 = 0xD247;
+//! Addresses in the game's memory and rom. Most of these are different in each version of the
+//! game, so they are kept in an `Offsets` table that is chosen based on the rom's header.
+use std::{fmt, sync::OnceLock};
+
+// Hardware registers
+pub const SERIAL_DATA: u16 = 0xFF01;
+pub const SERIAL_CONTROL: u16 = 0xFF02;
+pub const INTERRUPT_FLAGS: u16 = 0xFF0F;
+
+// The sizes of entries in the game's tables, which are the same in every version
+pub const PARTY_POKE_SIZE: u16 = 0x2C;
+pub const NAME_LENGTH: u16 = 11;
+pub const BASE_STATS_SIZE: u16 = 28;
+pub const MONSTER_NAME_LENGTH: u16 = 10;
+pub const MOVE_SIZE: u16 = 6;
+
+/// The addresses that depend on the version of the game
+#[derive(Clone, Debug)]
+pub struct Offsets {
+    // Player positional data
+    pub map_id: u16,
+    pub map_y: u16,
+    pub map_x: u16,
+    pub player_dy: u16,
+    pub player_dx: u16,
+
+    // The direction which the player is facing (0: down, 4: up, 8: left, 12: right)
+    pub player_dir: u16,
+
+    // When a player moves, this value counts down from 8 to 0
+    pub walk_counter: u16,
+
+    // General player data
+    pub player_name_start: u16,
+
+    // The address of the player spritesheet encoded as 2bpp in the rom
+    pub red_sprite_addr: u16,
+    pub red_sprite_bank: usize,
+    pub blue_sprite_addr: u16,
+    pub blue_sprite_bank: usize,
+    pub oak_sprite_addr: u16,
+    pub oak_sprite_bank: usize,
+
+    // The address of the main font encoded as a 1bpp sprite in the rom
+    pub font_addr: u16,
+    pub font_bank: usize,
+
+    // The address of the textbox border encoded as 2bpp sprite in the rom
+    pub border_addr: u16,
+    pub border_bank: usize,
+
+    // Tables used to locate the header of each map. The pointer table is in bank 0 and is indexed
+    // by the map id, the bank table gives the bank that each header is stored in.
+    pub map_header_pointers: u16,
+    pub map_header_banks: u16,
+    pub map_header_banks_bank: usize,
+
+    // The location of the tile map
+    pub tile_map: u16,
+
+    // Useful addresses for hacks
+    pub loaded_rom_bank: u16,
+    pub frame_counter: u16,
+    pub bank_switch: u16,
+
+    // Addresses for sprite check hack
+    pub num_sprites: u16,
+    pub overworld_loop_start: u16,
+    pub sprite_check_start: u16,
+    pub sprite_check_exit_1: u16,
+    pub sprite_check_exit_2: u16,
+    pub sprite_index: u16,
+
+    // Addresses for sprite update hack
+    pub clear_sprites: u16,
+    pub update_sprites: u16,
+    pub sprites_enabled: u16,
+
+    // Addresses for display text hack
+    pub display_text_id: u16,
+    pub display_text_id_after_init: u16,
+    pub display_text_setup_done: u16,
+    pub get_next_char_1: u16,
+    pub get_next_char_2: u16,
+    pub text_processor_end: u16,
+
+    // Addresses for battle hack
+    pub trainer_class: u16,
+    pub trainer_name: u16,
+    pub trainer_num: u16,
+    pub active_battle: u16,
+    pub currrent_opponent: u16,
+    pub current_enemy_level: u16,
+    pub current_enemy_nick: u16,
+    pub battle_type: u16,
+    pub link_state: u16,
+
+    // Addresses for link battle hack. During a link battle the game calls `Serial_ExchangeNybble`
+    // once per frame until the opponent's action has been received.
+    pub serial_exchange_nybble: u16,
+    pub serial_connection_status: u16,
+    pub serial_receive_data: u16,
+    pub nybble_receive_data: u16,
+    pub nybble_send_data: u16,
+    pub link_random_number_index: u16,
+    pub link_random_numbers: u16,
+
+    // The Prof. Oak battle is unused by the game, so it is a convenient place to replace with our
+    // battle data.
+    pub prof_oak_data_addr: u16,
+    pub prof_oak_data_bank: usize,
+
+    // Addresses for battle data
+    pub player_battle_data_start: u16,
+    pub enemy_battle_data_start: u16,
+    pub enemy_name_start: u16,
+
+    // Addresses for specific party data
+    pub party_count: u16,
+    pub party_poke_1: u16,
+    pub party_poke_2: u16,
+    pub party_poke_3: u16,
+    pub party_poke_4: u16,
+    pub party_poke_5: u16,
+    pub party_poke_6: u16,
+    pub party_species: u16,
+    pub party_ot_names: u16,
+    pub party_nicknames: u16,
+
+    // Bit flags for each pokemon in the pokedex
+    pub pokedex_owned: u16,
+    pub pokedex_seen: u16,
+
+    // Tables in the rom describing each species. The base stats are indexed by pokedex number,
+    // except for Mew which is stored separately. The other tables are indexed by the internal
+    // species id.
+    pub base_stats: u16,
+    pub base_stats_bank: usize,
+    pub mew_base_stats: u16,
+    pub mew_base_stats_bank: usize,
+    pub pokedex_order: u16,
+    pub pokedex_order_bank: usize,
+    pub evos_moves_pointers: u16,
+    pub evos_moves_bank: usize,
+    pub monster_names: u16,
+    pub monster_names_bank: usize,
+
+    // The table of moves, indexed by the move id starting from 1
+    pub moves: u16,
+    pub moves_bank: usize,
+
+    // The move taught by each TM, followed by the HMs
+    pub technical_machines: u16,
+    pub technical_machines_bank: usize,
+
+    // Lists of names, stored one after another each ending with a terminator. The names of types
+    // are instead found through a table of pointers, indexed by the type.
+    pub move_names: u16,
+    pub move_names_bank: usize,
+    pub item_names: u16,
+    pub item_names_bank: usize,
+    pub trainer_names: u16,
+    pub trainer_names_bank: usize,
+    pub type_names: u16,
+    pub type_names_bank: usize,
+}
+
+/// Red and Blue are built from the same code, so everything is at the same address in both
+pub static RED_BLUE: Offsets = Offsets {
+    map_id: 0xD35E,
+    map_y: 0xD361,
+    map_x: 0xD362,
+    player_dy: 0xC103,
+    player_dx: 0xC105,
+    player_dir: 0xC109,
+    walk_counter: 0xCFC5,
+    player_name_start: 0xD158,
+    red_sprite_addr: 0x4180,
+    red_sprite_bank: 5,
+    blue_sprite_addr: 0x4300,
+    blue_sprite_bank: 5,
+    oak_sprite_addr: 0x4480,
+    oak_sprite_bank: 5,
+    font_addr: 0x5A80,
+    font_bank: 4,
+    border_addr: 0x6288 + 2 * 8 * (4 * 6 + 1),
+    border_bank: 4,
+    map_header_pointers: 0x01AE,
+    map_header_banks: 0x423D,
+    map_header_banks_bank: 3,
+    tile_map: 0xC3A0,
+    loaded_rom_bank: 0xFFB8,
+    frame_counter: 0xFFD5,
+    bank_switch: 0x35D6,
+    num_sprites: 0xD4E1,
+    overworld_loop_start: 0x03FF,
+    sprite_check_start: 0x0B23,
+    sprite_check_exit_1: 0x0BA0,
+    sprite_check_exit_2: 0x0BC4,
+    sprite_index: 0xFF8C,
+    clear_sprites: 0x0082,
+    update_sprites: 0x2429,
+    sprites_enabled: 0xCFCB,
+    display_text_id: 0x2920,
+    display_text_id_after_init: 0x292B,
+    display_text_setup_done: 0x29CD,
+    get_next_char_1: 0x1B55,
+    get_next_char_2: 0x1956,
+    text_processor_end: 0x1B5E,
+    trainer_class: 0xD031,
+    trainer_name: 0xD04A,
+    trainer_num: 0xD05D,
+    active_battle: 0xD057,
+    currrent_opponent: 0xD059,
+    current_enemy_level: 0xD127,
+    current_enemy_nick: 0xCFDA,
+    battle_type: 0xD05A,
+    link_state: 0xD12B,
+    serial_exchange_nybble: 0x2247,
+    serial_connection_status: 0xFFAA,
+    serial_receive_data: 0xFFAD,
+    nybble_receive_data: 0xCC3E,
+    nybble_send_data: 0xCC42,
+    link_random_number_index: 0xCCDE,
+    link_random_numbers: 0xD148,
+    prof_oak_data_addr: 0x621D,
+    prof_oak_data_bank: 0xE,
+    player_battle_data_start: 0xD163,
+    enemy_battle_data_start: 0xD89C,
+    enemy_name_start: 0xD887,
+    party_count: 0xD163,
+    party_poke_1: 0xD16B,
+    party_poke_2: 0xD197,
+    party_poke_3: 0xD1C3,
+    party_poke_4: 0xD1EF,
+    party_poke_5: 0xD21B,
+    party_poke_6: 0xD247,
+    party_species: 0xD164,
+    party_ot_names: 0xD273,
+    party_nicknames: 0xD2B5,
+    pokedex_owned: 0xD2F7,
+    pokedex_seen: 0xD30A,
+    base_stats: 0x43DE,
+    base_stats_bank: 0xE,
+    mew_base_stats: 0x425B,
+    mew_base_stats_bank: 0x1,
+    pokedex_order: 0x5024,
+    pokedex_order_bank: 0x10,
+    evos_moves_pointers: 0x705C,
+    evos_moves_bank: 0xE,
+    monster_names: 0x421E,
+    monster_names_bank: 0x7,
+    moves: 0x4000,
+    moves_bank: 0xE,
+    technical_machines: 0x7773,
+    technical_machines_bank: 0x4,
+    move_names: 0x4000,
+    move_names_bank: 0x2C,
+    item_names: 0x472B,
+    item_names_bank: 0x1,
+    trainer_names: 0x59FF,
+    trainer_names_bank: 0xE,
+    type_names: 0x7DAE,
+    type_names_bank: 0x9,
+};
+
+// The location of each field in the rom's header, which describes the cartridge
+const HEADER_TITLE: usize = 0x134;
+const HEADER_TITLE_LENGTH: usize = 16;
+const HEADER_VERSION: usize = 0x14C;
+const HEADER_GLOBAL_CHECKSUM: usize = 0x14E;
+
+/// A release of the game, identified by the header of its rom
+pub struct Version {
+    pub name: &'static str,
+    pub title: &'static str,
+    pub version: u8,
+    /// The checksum of an unmodified rom, or `None` to recognise the version from its title alone
+    pub global_checksum: Option<u16>,
+    /// The addresses used by this version, or `None` if it is not supported yet
+    pub offsets: Option<&'static Offsets>,
+}
+
+/// Every version of the game that can be recognised
+pub static VERSIONS: &[Version] = &[
+    Version {
+        name: "Pokemon Red",
+        title: "POKEMON RED",
+        version: 0,
+        global_checksum: Some(0x91E6),
+        offsets: Some(&RED_BLUE),
+    },
+    Version {
+        name: "Pokemon Blue",
+        title: "POKEMON BLUE",
+        version: 0,
+        global_checksum: Some(0x9D0A),
+        offsets: Some(&RED_BLUE),
+    },
+    Version {
+        name: "Pokemon Yellow",
+        title: "POKEMON YELLOW",
+        version: 0,
+        global_checksum: None,
+        offsets: None,
+    },
+];
+
+/// The parts of the rom's header used to tell which version of the game it is
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Header {
+    pub title: String,
+    pub version: u8,
+    pub global_checksum: u16,
+}
+
+impl Header {
+    /// Read the header of a rom, returns `None` if the rom is too small to have one
+    pub fn read(rom: &[u8]) -> Option<Header> {
+        if rom.len() < HEADER_GLOBAL_CHECKSUM + 2 {
+            return None;
+        }
+
+        // The title is padded with zeros
+        let title = &rom[HEADER_TITLE..HEADER_TITLE + HEADER_TITLE_LENGTH];
+        let title = title.iter().take_while(|&&c| c != 0).map(|&c| c as char).collect();
+        let checksum = &rom[HEADER_GLOBAL_CHECKSUM..HEADER_GLOBAL_CHECKSUM + 2];
+        Some(Header {
+            title,
+            version: rom[HEADER_VERSION],
+            global_checksum: u16::from_be_bytes([checksum[0], checksum[1]]),
+        })
+    }
+}
+
+#[derive(Debug)]
+pub enum UnknownRom {
+    /// The file is too small to be a rom
+    NoHeader,
+    /// The rom is not a version of the game that we know about, or has been modified
+    Unrecognised(Header),
+    /// The rom is a version of the game that does not work yet
+    Unsupported(&'static str),
+    /// The addresses for a rom have already been chosen, and cannot change once the game is running
+    AlreadySelected,
+}
+
+impl std::error::Error for UnknownRom {}
+
+impl fmt::Display for UnknownRom {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            UnknownRom::NoHeader => f.write_str("the rom is too small to be a Game Boy rom"),
+            UnknownRom::Unrecognised(header) => write!(
+                f,
+                "the rom is not a supported version of the game (title: {:?}, version: {}, \
+                checksum: {:04X}). Only unmodified copies of Pokemon Red and Blue are supported.",
+                header.title, header.version, header.global_checksum
+            ),
+            UnknownRom::Unsupported(name) => write!(f, "{} is not supported yet", name),
+            UnknownRom::AlreadySelected => {
+                f.write_str("the addresses for another rom have already been chosen")
+            }
+        }
+    }
+}
+
+/// Work out which version of the game a rom is from its header
+pub fn detect(rom: &[u8]) -> Result<&'static Version, UnknownRom> {
+    let header = Header::read(rom).ok_or(UnknownRom::NoHeader)?;
+    VERSIONS
+        .iter()
+        .find(|version| {
+            version.title == header.title
+                && version.version == header.version
+                && version.global_checksum.is_none_or(|sum| sum == header.global_checksum)
+        })
+        .ok_or(UnknownRom::Unrecognised(header))
+}
+
+static SELECTED: OnceLock<&'static Offsets> = OnceLock::new();
+
+/// Use `offsets` for every address from now on. This can only be done once, before anything reads
+/// from the game, and returns false if a table has already been chosen.
+pub fn select(offsets: &'static Offsets) -> bool {
+    SELECTED.set(offsets).is_ok()
+}
+
+/// Whether a table has been chosen with `select`
+pub fn is_selected() -> bool {
+    SELECTED.get().is_some()
+}
+
+/// Check that a rom is a version of the game that we have addresses for, and use those addresses
+pub fn select_for_rom(rom: &[u8]) -> Result<&'static Version, UnknownRom> {
+    let version = detect(rom)?;
+    match version.offsets {
+        Some(offsets) if select(offsets) => Ok(version),
+        Some(_) => Err(UnknownRom::AlreadySelected),
+        None => Err(UnknownRom::Unsupported(version.name)),
+    }
+}
+
+/// The addresses for the version of the game being played, which are Red and Blue's until another
+/// table has been selected
+pub fn get() -> &'static Offsets {
+    SELECTED.get().copied().unwrap_or(&RED_BLUE)
+}
@@ -0,0 +1,499 @@
+//! Reading and writing the game's save file (the 32 KiB SRAM image) without running the emulator.
+//!
+//! The save is split into banks: bank 1 holds the player's data, the party and the current PC box,
+//! while banks 2 and 3 hold the twelve PC boxes. Each part of the save is protected by a checksum,
+//! which the game checks before loading it.
+use std::fmt;
+
+use crate::{
+    data::{PokemonData, PokemonRecord},
+    text,
+};
+
+/// The size of the save file
+pub const SAVE_SIZE: usize = 0x8000;
+
+pub const NUM_BOXES: usize = 12;
+pub const BOX_CAPACITY: usize = 20;
+pub const PARTY_CAPACITY: usize = 6;
+pub const BAG_CAPACITY: usize = 20;
+pub const PC_ITEM_CAPACITY: usize = 50;
+pub const POKEDEX_LENGTH: usize = 151;
+
+const NAME_LENGTH: usize = 11;
+pub(crate) const PARTY_POKEMON_SIZE: usize = 0x2C;
+/// Pokemon in the PC do not store their stats, which are recalculated when they are withdrawn
+const BOX_POKEMON_SIZE: usize = 0x21;
+
+// Offsets in bank 1
+const PLAYER_NAME: usize = 0x2598;
+const POKEDEX_OWNED: usize = 0x25A3;
+const POKEDEX_SEEN: usize = 0x25B6;
+const BAG_ITEMS: usize = 0x25C9;
+const MONEY: usize = 0x25F3;
+const RIVAL_NAME: usize = 0x25F6;
+const BADGES: usize = 0x2602;
+const PLAYER_ID: usize = 0x2605;
+const PC_ITEMS: usize = 0x27E6;
+const CURRENT_BOX_NUMBER: usize = 0x284C;
+const PLAY_TIME: usize = 0x2CED;
+const PARTY: usize = 0x2F2C;
+const CURRENT_BOX: usize = 0x30C0;
+const MAIN_CHECKSUM: usize = 0x3523;
+
+// Boxes 1-6 are in bank 2 and boxes 7-12 are in bank 3, followed by a checksum of all the boxes in
+// the bank and then a checksum for each box.
+const BOX_BANKS: [usize; 2] = [0x4000, 0x6000];
+const BOXES_PER_BANK: usize = 6;
+const BOX_SIZE: usize = 0x462;
+
+/// Set in the current box number once the boxes have been cleared for the first time
+const BOXES_INITIALIZED: u8 = 0x80;
+
+const LIST_TERMINATOR: u8 = 0xFF;
+
+#[derive(Debug)]
+pub enum SaveError {
+    /// The save file is not the size of the game's SRAM
+    InvalidSize(usize),
+    /// The checksum of the player's data does not match, so the game would not load this save
+    InvalidChecksum,
+}
+
+impl std::error::Error for SaveError {}
+
+impl fmt::Display for SaveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SaveError::InvalidSize(size) => {
+                write!(f, "save file is {} bytes, expected {} bytes", size, SAVE_SIZE)
+            }
+            SaveError::InvalidChecksum => f.write_str("save file has an invalid checksum"),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Item {
+    pub id: u8,
+    pub quantity: u8,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct PlayTime {
+    pub hours: u8,
+    pub minutes: u8,
+    pub seconds: u8,
+}
+
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub struct SaveData {
+    pub player_name: Vec<u8>,
+    pub rival_name: Vec<u8>,
+    pub player_id: u16,
+    pub money: u32,
+    /// One bit for each badge, starting with the Boulder Badge in the lowest bit
+    pub badges: u8,
+    pub play_time: PlayTime,
+    /// Indexed by pokedex number minus one
+    pub pokedex_owned: Vec<bool>,
+    pub pokedex_seen: Vec<bool>,
+    pub bag: Vec<Item>,
+    pub pc_items: Vec<Item>,
+    pub party: Vec<PokemonRecord>,
+    /// The index of the box that the PC is currently using
+    pub current_box: u8,
+    pub boxes: Vec<Vec<PokemonRecord>>,
+
+    /// The original save file, so that everything that is not parsed is kept when writing it back
+    #[serde(skip)]
+    raw: Vec<u8>,
+}
+
+impl SaveData {
+    /// Parse a save file
+    pub fn parse(raw: &[u8]) -> Result<SaveData, SaveError> {
+        if raw.len() != SAVE_SIZE {
+            return Err(SaveError::InvalidSize(raw.len()));
+        }
+        if checksum(&raw[PLAYER_NAME..MAIN_CHECKSUM]) != raw[MAIN_CHECKSUM] {
+            return Err(SaveError::InvalidChecksum);
+        }
+
+        let current_box = raw[CURRENT_BOX_NUMBER] & !BOXES_INITIALIZED;
+        let boxes_initialized = raw[CURRENT_BOX_NUMBER] & BOXES_INITIALIZED != 0;
+        let boxes = (0..NUM_BOXES)
+            .map(|i| {
+                // The current box is only copied back into its bank when the player changes box
+                if i == current_box as usize {
+                    read_pokemon_list(&raw[CURRENT_BOX..], BOX_CAPACITY, BOX_POKEMON_SIZE)
+                }
+                // The box banks hold whatever was in SRAM until the game first clears them
+                else if boxes_initialized {
+                    read_pokemon_list(&raw[box_offset(i)..], BOX_CAPACITY, BOX_POKEMON_SIZE)
+                }
+                else {
+                    vec![]
+                }
+            })
+            .collect();
+
+        Ok(SaveData {
+            player_name: read_name(&raw[PLAYER_NAME..]),
+            rival_name: read_name(&raw[RIVAL_NAME..]),
+            player_id: u16::from_be_bytes([raw[PLAYER_ID], raw[PLAYER_ID + 1]]),
+            money: read_bcd(&raw[MONEY..MONEY + 3]),
+            badges: raw[BADGES],
+            play_time: PlayTime {
+                hours: raw[PLAY_TIME],
+                minutes: raw[PLAY_TIME + 2],
+                seconds: raw[PLAY_TIME + 3],
+            },
+            pokedex_owned: read_flags(&raw[POKEDEX_OWNED..], POKEDEX_LENGTH),
+            pokedex_seen: read_flags(&raw[POKEDEX_SEEN..], POKEDEX_LENGTH),
+            bag: read_items(&raw[BAG_ITEMS..], BAG_CAPACITY),
+            pc_items: read_items(&raw[PC_ITEMS..], PC_ITEM_CAPACITY),
+            party: read_pokemon_list(&raw[PARTY..], PARTY_CAPACITY, PARTY_POKEMON_SIZE),
+            current_box,
+            boxes,
+            raw: raw.to_vec(),
+        })
+    }
+
+    /// Write the save back out, updating all of the checksums. Lists that are longer than the
+    /// game allows are truncated.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut raw = self.raw.clone();
+        raw.resize(SAVE_SIZE, 0);
+
+        write_name(&mut raw[PLAYER_NAME..], &self.player_name);
+        write_name(&mut raw[RIVAL_NAME..], &self.rival_name);
+        raw[PLAYER_ID..PLAYER_ID + 2].copy_from_slice(&self.player_id.to_be_bytes());
+        write_bcd(&mut raw[MONEY..MONEY + 3], self.money.min(999999));
+        raw[BADGES] = self.badges;
+        raw[PLAY_TIME] = self.play_time.hours;
+        raw[PLAY_TIME + 2] = self.play_time.minutes;
+        raw[PLAY_TIME + 3] = self.play_time.seconds;
+        write_flags(&mut raw[POKEDEX_OWNED..], &self.pokedex_owned, POKEDEX_LENGTH);
+        write_flags(&mut raw[POKEDEX_SEEN..], &self.pokedex_seen, POKEDEX_LENGTH);
+        write_items(&mut raw[BAG_ITEMS..], &self.bag, BAG_CAPACITY);
+        write_items(&mut raw[PC_ITEMS..], &self.pc_items, PC_ITEM_CAPACITY);
+        write_pokemon_list(&mut raw[PARTY..], &self.party, PARTY_CAPACITY, PARTY_POKEMON_SIZE);
+
+        // Every box is written below, so the banks no longer need to be cleared by the game. The
+        // flag is only set when it matters, so that unchanged saves are written back unchanged.
+        let current_box = (self.current_box as usize).min(NUM_BOXES - 1);
+        let mut initialized = raw[CURRENT_BOX_NUMBER] & BOXES_INITIALIZED;
+        if self.boxes.iter().enumerate().any(|(i, box_)| i != current_box && !box_.is_empty()) {
+            initialized = BOXES_INITIALIZED;
+        }
+        raw[CURRENT_BOX_NUMBER] = current_box as u8 | initialized;
+
+        let empty_box = vec![];
+        for i in 0..NUM_BOXES {
+            let pokemon = self.boxes.get(i).unwrap_or(&empty_box);
+            write_pokemon_list(&mut raw[box_offset(i)..], pokemon, BOX_CAPACITY, BOX_POKEMON_SIZE);
+            if i == current_box {
+                let current = &mut raw[CURRENT_BOX..];
+                write_pokemon_list(current, pokemon, BOX_CAPACITY, BOX_POKEMON_SIZE);
+            }
+        }
+
+        raw[MAIN_CHECKSUM] = checksum(&raw[PLAYER_NAME..MAIN_CHECKSUM]);
+        for bank in BOX_BANKS {
+            let boxes_end = bank + BOXES_PER_BANK * BOX_SIZE;
+            raw[boxes_end] = checksum(&raw[bank..boxes_end]);
+            for i in 0..BOXES_PER_BANK {
+                let start = bank + i * BOX_SIZE;
+                raw[boxes_end + 1 + i] = checksum(&raw[start..start + BOX_SIZE]);
+            }
+        }
+
+        raw
+    }
+}
+
+/// The game's checksum: the complement of the sum of every byte
+fn checksum(data: &[u8]) -> u8 {
+    !data.iter().fold(0_u8, |sum, &byte| sum.wrapping_add(byte))
+}
+
+fn box_offset(index: usize) -> usize {
+    BOX_BANKS[index / BOXES_PER_BANK] + (index % BOXES_PER_BANK) * BOX_SIZE
+}
+
+fn read_name(data: &[u8]) -> Vec<u8> {
+    data[..NAME_LENGTH].iter().copied().take_while(|&c| c != text::special::TERMINATOR).collect()
+}
+
+/// Write a name, padding it with `TERMINATOR` to fill the name's space
+fn write_name(data: &mut [u8], name: &[u8]) {
+    let name = name.iter().copied().take(NAME_LENGTH - 1);
+    let padding = std::iter::repeat(text::special::TERMINATOR);
+    for (dst, src) in data[..NAME_LENGTH].iter_mut().zip(name.chain(padding)) {
+        *dst = src;
+    }
+}
+
+/// Read a binary-coded decimal number, which the game uses for money
+fn read_bcd(data: &[u8]) -> u32 {
+    data.iter().fold(0, |value, &byte| value * 100 + (byte >> 4) as u32 * 10 + (byte & 0xF) as u32)
+}
+
+fn write_bcd(data: &mut [u8], mut value: u32) {
+    for byte in data.iter_mut().rev() {
+        let digits = value % 100;
+        *byte = (((digits / 10) << 4) | (digits % 10)) as u8;
+        value /= 100;
+    }
+}
+
+fn read_flags(data: &[u8], count: usize) -> Vec<bool> {
+    (0..count).map(|i| data[i / 8] & (1 << (i % 8)) != 0).collect()
+}
+
+fn write_flags(data: &mut [u8], flags: &[bool], count: usize) {
+    for i in 0..count {
+        let bit = 1 << (i % 8);
+        match flags.get(i) {
+            Some(true) => data[i / 8] |= bit,
+            _ => data[i / 8] &= !bit,
+        }
+    }
+}
+
+/// Read a list of items: the number of items, followed by an id and quantity for each item
+fn read_items(data: &[u8], capacity: usize) -> Vec<Item> {
+    let count = (data[0] as usize).min(capacity);
+    (0..count).map(|i| Item { id: data[1 + 2 * i], quantity: data[2 + 2 * i] }).collect()
+}
+
+fn write_items(data: &mut [u8], items: &[Item], capacity: usize) {
+    let items = &items[..items.len().min(capacity)];
+    data[0] = items.len() as u8;
+    for (i, item) in items.iter().enumerate() {
+        data[1 + 2 * i] = item.id;
+        data[2 + 2 * i] = item.quantity;
+    }
+    data[1 + 2 * items.len()] = LIST_TERMINATOR;
+}
+
+/// Read a list of pokemon in the layout used by the party and the PC boxes: the number of
+/// pokemon, a list of species, then the data, original trainer names and nicknames of every
+/// pokemon.
+fn read_pokemon_list(data: &[u8], capacity: usize, pokemon_size: usize) -> Vec<PokemonRecord> {
+    let count = (data[0] as usize).min(capacity);
+    let data_start = capacity + 2;
+    let ot_names_start = data_start + capacity * pokemon_size;
+    let nicknames_start = ot_names_start + capacity * NAME_LENGTH;
+
+    (0..count)
+        .map(|i| PokemonRecord {
+            data: read_pokemon(&data[data_start + i * pokemon_size..], pokemon_size),
+            ot_name: read_name(&data[ot_names_start + i * NAME_LENGTH..]),
+            nickname: read_name(&data[nicknames_start + i * NAME_LENGTH..]),
+        })
+        .collect()
+}
+
+fn write_pokemon_list(
+    data: &mut [u8],
+    pokemon: &[PokemonRecord],
+    capacity: usize,
+    pokemon_size: usize,
+) {
+    let pokemon = &pokemon[..pokemon.len().min(capacity)];
+    let data_start = capacity + 2;
+    let ot_names_start = data_start + capacity * pokemon_size;
+    let nicknames_start = ot_names_start + capacity * NAME_LENGTH;
+
+    data[0] = pokemon.len() as u8;
+    for (i, record) in pokemon.iter().enumerate() {
+        data[1 + i] = record.data.species;
+        write_pokemon(&mut data[data_start + i * pokemon_size..], &record.data, pokemon_size);
+        write_name(&mut data[ot_names_start + i * NAME_LENGTH..], &record.ot_name);
+        write_name(&mut data[nicknames_start + i * NAME_LENGTH..], &record.nickname);
+    }
+    data[1 + pokemon.len()] = LIST_TERMINATOR;
+}
+
+pub(crate) fn read_pokemon(data: &[u8], size: usize) -> PokemonData {
+    let word = |offset: usize| u16::from_be_bytes([data[offset], data[offset + 1]]);
+
+    // Pokemon in the PC keep their level in the byte before their status instead
+    let has_stats = size == PARTY_POKEMON_SIZE;
+    PokemonData {
+        species: data[0],
+        hp: word(1),
+        unknown: data[3],
+        status: data[4],
+        type1: data[5],
+        type2: data[6],
+        catch_rate: data[7],
+        moves: (data[8], data[9], data[10], data[11]),
+        ot_id: word(12),
+
+        exp: (data[14], data[15], data[16]),
+        hp_ev: word(17),
+        attack_ev: word(19),
+        defense_ev: word(21),
+        speed_ev: word(23),
+        special_ev: word(25),
+        individual_values: (data[27], data[28]),
+        move_pp: (data[29], data[30], data[31], data[32]),
+
+        level: if has_stats { data[33] } else { data[3] },
+        max_hp: if has_stats { word(34) } else { 0 },
+        attack: if has_stats { word(36) } else { 0 },
+        defense: if has_stats { word(38) } else { 0 },
+        speed: if has_stats { word(40) } else { 0 },
+        special: if has_stats { word(42) } else { 0 },
+    }
+}
+
+pub(crate) fn write_pokemon(data: &mut [u8], pokemon: &PokemonData, size: usize) {
+    let mut word = |offset: usize, value: u16| {
+        data[offset..offset + 2].copy_from_slice(&value.to_be_bytes());
+    };
+    word(1, pokemon.hp);
+    word(12, pokemon.ot_id);
+    word(17, pokemon.hp_ev);
+    word(19, pokemon.attack_ev);
+    word(21, pokemon.defense_ev);
+    word(23, pokemon.speed_ev);
+    word(25, pokemon.special_ev);
+    if size == PARTY_POKEMON_SIZE {
+        word(34, pokemon.max_hp);
+        word(36, pokemon.attack);
+        word(38, pokemon.defense);
+        word(40, pokemon.speed);
+        word(42, pokemon.special);
+    }
+
+    data[0] = pokemon.species;
+    data[3] = if size == PARTY_POKEMON_SIZE { pokemon.unknown } else { pokemon.level };
+    data[4] = pokemon.status;
+    data[5] = pokemon.type1;
+    data[6] = pokemon.type2;
+    data[7] = pokemon.catch_rate;
+    let (move1, move2, move3, move4) = pokemon.moves;
+    data[8..12].copy_from_slice(&[move1, move2, move3, move4]);
+    let (exp1, exp2, exp3) = pokemon.exp;
+    data[14..17].copy_from_slice(&[exp1, exp2, exp3]);
+    data[27] = pokemon.individual_values.0;
+    data[28] = pokemon.individual_values.1;
+    let (pp1, pp2, pp3, pp4) = pokemon.move_pp;
+    data[29..33].copy_from_slice(&[pp1, pp2, pp3, pp4]);
+    if size == PARTY_POKEMON_SIZE {
+        data[33] = pokemon.level;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A save with empty lists, initialized boxes and a valid checksum
+    fn blank_save() -> Vec<u8> {
+        let mut raw = vec![0; SAVE_SIZE];
+        raw[CURRENT_BOX_NUMBER] = BOXES_INITIALIZED;
+        raw[MAIN_CHECKSUM] = checksum(&raw[PLAYER_NAME..MAIN_CHECKSUM]);
+        raw
+    }
+
+    fn pokemon(species: u8, level: u8, nickname: &str) -> PokemonRecord {
+        let mut data = read_pokemon(&[0; PARTY_POKEMON_SIZE], PARTY_POKEMON_SIZE);
+        data.species = species;
+        data.level = level;
+        data.hp = 20;
+        data.moves = (0x21, 0x2D, 0, 0);
+        data.individual_values = (0xA5, 0x3C);
+        data.max_hp = 25;
+        data.attack = 12;
+        PokemonRecord {
+            data,
+            ot_name: text::Encoder::new("RED").collect(),
+            nickname: text::Encoder::new(nickname).collect(),
+        }
+    }
+
+    fn names(pokemon: &[PokemonRecord]) -> Vec<String> {
+        pokemon.iter().map(|record| text::decode(&record.nickname)).collect()
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut save = SaveData::parse(&blank_save()).unwrap();
+        save.player_name = text::Encoder::new("ASH").collect();
+        save.money = 123456;
+        save.badges = 0b101;
+        save.pokedex_owned[24] = true;
+        save.bag = vec![Item { id: 0x04, quantity: 5 }];
+        save.party = vec![pokemon(0x54, 5, "PIKACHU"), pokemon(0x99, 7, "BULBY")];
+        save.current_box = 2;
+        save.boxes[2] = vec![pokemon(0xA5, 3, "RATTATA")];
+        save.boxes[9] = vec![pokemon(0x24, 4, "PIDGEY"), pokemon(0x70, 9, "WEEDLE")];
+
+        let raw = save.to_bytes();
+        let parsed = SaveData::parse(&raw).unwrap();
+        assert_eq!(text::decode(&parsed.player_name), "ASH");
+        assert_eq!(parsed.money, 123456);
+        assert_eq!(parsed.badges, 0b101);
+        assert!(parsed.pokedex_owned[24] && !parsed.pokedex_owned[23]);
+        assert_eq!(parsed.bag, save.bag);
+        assert!(parsed.party == save.party);
+        assert_eq!(parsed.current_box, 2);
+        assert_eq!(names(&parsed.boxes[2]), ["RATTATA"]);
+        assert_eq!(names(&parsed.boxes[9]), ["PIDGEY", "WEEDLE"]);
+        assert_eq!(parsed.boxes[9][1].data.level, 9);
+        assert!(parsed.boxes.iter().enumerate().all(|(i, b)| b.is_empty() || i == 2 || i == 9));
+
+        // Writing a parsed save back out does not change it
+        assert_eq!(parsed.to_bytes(), raw);
+    }
+
+    #[test]
+    fn checksums_are_updated() {
+        let mut save = SaveData::parse(&blank_save()).unwrap();
+        save.boxes[7] = vec![pokemon(0x54, 5, "PIKACHU")];
+        let raw = save.to_bytes();
+
+        assert_eq!(raw[MAIN_CHECKSUM], checksum(&raw[PLAYER_NAME..MAIN_CHECKSUM]));
+        for bank in BOX_BANKS {
+            let boxes_end = bank + BOXES_PER_BANK * BOX_SIZE;
+            assert_eq!(raw[boxes_end], checksum(&raw[bank..boxes_end]));
+            for i in 0..BOXES_PER_BANK {
+                let start = bank + i * BOX_SIZE;
+                assert_eq!(raw[boxes_end + 1 + i], checksum(&raw[start..start + BOX_SIZE]));
+            }
+        }
+    }
+
+    #[test]
+    fn invalid_saves_rejected() {
+        let mut raw = blank_save();
+        raw[MONEY] = 0x12;
+        assert!(matches!(SaveData::parse(&raw), Err(SaveError::InvalidChecksum)));
+        assert!(matches!(SaveData::parse(&raw[..0x2000]), Err(SaveError::InvalidSize(0x2000))));
+    }
+
+    #[test]
+    fn uninitialized_boxes_are_empty() {
+        // A new game's box banks are whatever was in SRAM before, until the game clears them
+        let mut raw = vec![0xFF; SAVE_SIZE];
+        raw[PLAYER_NAME..MAIN_CHECKSUM].fill(0);
+        raw[CURRENT_BOX_NUMBER] = 0;
+        raw[MAIN_CHECKSUM] = checksum(&raw[PLAYER_NAME..MAIN_CHECKSUM]);
+
+        let save = SaveData::parse(&raw).unwrap();
+        assert!(save.boxes.iter().all(|b| b.is_empty()));
+        assert_eq!(save.to_bytes()[CURRENT_BOX_NUMBER], 0);
+
+        // Filling a box that is not the current box makes the game keep the banks as they are
+        let mut save = save;
+        save.boxes[4] = vec![pokemon(0x54, 5, "PIKACHU")];
+        let parsed = SaveData::parse(&save.to_bytes()).unwrap();
+        assert_eq!(names(&parsed.boxes[4]), ["PIKACHU"]);
+        assert!(parsed.boxes[5].is_empty());
+    }
+}
@@ -0,0 +1,345 @@
+//! Reading addresses from the symbol file produced when building the game from its disassembly
+//! (https://github.com/pret/pokered), so that modified versions of the game can be played without
+//! updating the built-in tables in `offsets`.
+use std::{collections::HashMap, error::Error, fmt, fs, io, path::Path};
+
+use crate::offsets::{self, Offsets, UnknownRom, Version};
+
+/// A label in the disassembly, and the addresses in `Offsets` that are found from it
+struct Symbol {
+    label: &'static str,
+    /// Added to the address of the label, for addresses of instructions next to a label
+    offset: i16,
+    addr: fn(&mut Offsets) -> &mut u16,
+    /// Where to store the bank of the label, for addresses in the rom's switchable banks
+    bank: Option<fn(&mut Offsets) -> &mut usize>,
+}
+
+macro_rules! symbol {
+    ($label:literal => $addr:ident) => {
+        symbol!($label + 0 => $addr)
+    };
+    ($label:literal + $offset:expr => $addr:ident) => {
+        Symbol { label: $label, offset: $offset, addr: |o| &mut o.$addr, bank: None }
+    };
+    ($label:literal - $offset:expr => $addr:ident) => {
+        symbol!($label + -$offset => $addr)
+    };
+    ($label:literal => $addr:ident, $bank:ident) => {
+        symbol!($label + 0 => $addr, $bank)
+    };
+    ($label:literal + $offset:expr => $addr:ident, $bank:ident) => {
+        Symbol {
+            label: $label,
+            offset: $offset,
+            addr: |o| &mut o.$addr,
+            bank: Some(|o| &mut o.$bank),
+        }
+    };
+}
+
+// Hooks in the middle of a function use the nearest local label, and only step over the
+// instructions between the label and the hook.
+static SYMBOLS: &[Symbol] = &[
+    symbol!("wCurMap" => map_id),
+    symbol!("wYCoord" => map_y),
+    symbol!("wXCoord" => map_x),
+    symbol!("wSpritePlayerStateData1YStepVector" => player_dy),
+    symbol!("wSpritePlayerStateData1XStepVector" => player_dx),
+    symbol!("wSpritePlayerStateData1FacingDirection" => player_dir),
+    symbol!("wWalkCounter" => walk_counter),
+    symbol!("wPlayerName" => player_name_start),
+    symbol!("RedSprite" => red_sprite_addr, red_sprite_bank),
+    symbol!("BlueSprite" => blue_sprite_addr, blue_sprite_bank),
+    symbol!("OakSprite" => oak_sprite_addr, oak_sprite_bank),
+    symbol!("FontGraphics" => font_addr, font_bank),
+    symbol!("TextBoxGraphics" + 2 * 8 * (4 * 6 + 1) => border_addr, border_bank),
+    symbol!("MapHeaderPointers" => map_header_pointers),
+    symbol!("MapHeaderBanks" => map_header_banks, map_header_banks_bank),
+    symbol!("wTileMap" => tile_map),
+    symbol!("hLoadedROMBank" => loaded_rom_bank),
+    symbol!("hFrameCounter" => frame_counter),
+    symbol!("Bankswitch" => bank_switch),
+    symbol!("wNumSprites" => num_sprites),
+    symbol!("OverworldLoop" => overworld_loop_start),
+    symbol!("IsSpriteInFrontOfPlayer2" => sprite_check_start),
+    // The `ret z` when there are no sprites, before setting up the loop over them
+    symbol!("IsSpriteInFrontOfPlayer2.spriteLoop" - 6 => sprite_check_exit_1),
+    // The `ret` after checking every sprite
+    symbol!("IsSpriteInFrontOfPlayer2.foundSpriteInFrontOfPlayer" - 1 => sprite_check_exit_2),
+    symbol!("hSpriteIndex" => sprite_index),
+    symbol!("ClearSprites" => clear_sprites),
+    symbol!("UpdateSprites" => update_sprites),
+    symbol!("wUpdateSpritesEnabled" => sprites_enabled),
+    symbol!("DisplayTextID" => display_text_id),
+    // Any point after `DisplayTextIDInit` and before the frame counter is set works for the hook
+    symbol!("DisplayTextID.skipSwitchToMapBank" => display_text_id_after_init),
+    // The call to `PrintText_NoCreatingTextBox`, followed by the check for holding the text open
+    symbol!("AfterDisplayingTextID" - 9 => display_text_setup_done),
+    // The `ld a, [de]` and `ld a, [hli]` that read the next character
+    symbol!("PlaceNextChar" => get_next_char_2),
+    symbol!("NextTextCommand" => get_next_char_1),
+    // The `ret` after reaching the end of the text
+    symbol!("NextTextCommand.TextCommand" - 1 => text_processor_end),
+    symbol!("wTrainerClass" => trainer_class),
+    symbol!("wTrainerName" => trainer_name),
+    symbol!("wTrainerNo" => trainer_num),
+    symbol!("wIsInBattle" => active_battle),
+    symbol!("wCurOpponent" => currrent_opponent),
+    symbol!("wCurEnemyLevel" => current_enemy_level),
+    symbol!("wEnemyMonNick" => current_enemy_nick),
+    symbol!("wBattleType" => battle_type),
+    symbol!("wLinkState" => link_state),
+    symbol!("Serial_ExchangeNybble" => serial_exchange_nybble),
+    symbol!("hSerialConnectionStatus" => serial_connection_status),
+    symbol!("hSerialReceiveData" => serial_receive_data),
+    symbol!("wSerialExchangeNybbleReceiveData" => nybble_receive_data),
+    symbol!("wSerialExchangeNybbleSendData" => nybble_send_data),
+    symbol!("wLinkBattleRandomNumberListIndex" => link_random_number_index),
+    symbol!("wLinkBattleRandomNumberList" => link_random_numbers),
+    symbol!("ProfOakData" => prof_oak_data_addr, prof_oak_data_bank),
+    symbol!("wPartyDataStart" => player_battle_data_start),
+    symbol!("wEnemyPartyCount" => enemy_battle_data_start),
+    symbol!("wLinkEnemyTrainerName" => enemy_name_start),
+    symbol!("wPartyCount" => party_count),
+    symbol!("wPartyMon1" => party_poke_1),
+    symbol!("wPartyMon2" => party_poke_2),
+    symbol!("wPartyMon3" => party_poke_3),
+    symbol!("wPartyMon4" => party_poke_4),
+    symbol!("wPartyMon5" => party_poke_5),
+    symbol!("wPartyMon6" => party_poke_6),
+    symbol!("wPartySpecies" => party_species),
+    symbol!("wPartyMonOT" => party_ot_names),
+    symbol!("wPartyMonNicks" => party_nicknames),
+    symbol!("wPokedexOwned" => pokedex_owned),
+    symbol!("wPokedexSeen" => pokedex_seen),
+    symbol!("BaseStats" => base_stats, base_stats_bank),
+    symbol!("MewBaseStats" => mew_base_stats, mew_base_stats_bank),
+    symbol!("PokedexOrder" => pokedex_order, pokedex_order_bank),
+    symbol!("EvosMovesPointerTable" => evos_moves_pointers, evos_moves_bank),
+    symbol!("MonsterNames" => monster_names, monster_names_bank),
+    symbol!("Moves" => moves, moves_bank),
+    symbol!("TechnicalMachines" => technical_machines, technical_machines_bank),
+    symbol!("MoveNames" => move_names, move_names_bank),
+    symbol!("ItemNames" => item_names, item_names_bank),
+    symbol!("TrainerNames" => trainer_names, trainer_names_bank),
+    symbol!("TypeNames" => type_names, type_names_bank),
+];
+
+#[derive(Debug)]
+pub enum SymbolError {
+    Io(io::Error),
+    /// A line that is not in the form `bank:address label`
+    InvalidLine(usize, String),
+}
+
+impl Error for SymbolError {}
+
+impl fmt::Display for SymbolError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SymbolError::Io(e) => write!(f, "failed to read the symbol file: {}", e),
+            SymbolError::InvalidLine(line, text) => {
+                write!(f, "invalid symbol on line {}: {:?}", line, text)
+            }
+        }
+    }
+}
+
+impl From<io::Error> for SymbolError {
+    fn from(e: io::Error) -> SymbolError {
+        SymbolError::Io(e)
+    }
+}
+
+/// The bank and address of every label in a symbol file
+pub struct SymbolFile {
+    symbols: HashMap<String, (usize, u16)>,
+}
+
+impl SymbolFile {
+    pub fn read(path: &Path) -> Result<SymbolFile, SymbolError> {
+        SymbolFile::parse(&fs::read_to_string(path)?)
+    }
+
+    /// Parse a symbol file where each line is `bank:address label` in hex, with `;` comments
+    pub fn parse(text: &str) -> Result<SymbolFile, SymbolError> {
+        let mut symbols = HashMap::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.split(';').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let invalid = || SymbolError::InvalidLine(i + 1, line.to_string());
+            let (location, label) = line.split_once(char::is_whitespace).ok_or_else(invalid)?;
+            let (bank, addr) = location.split_once(':').ok_or_else(invalid)?;
+            let bank = usize::from_str_radix(bank, 16).map_err(|_| invalid())?;
+            let addr = u16::from_str_radix(addr, 16).map_err(|_| invalid())?;
+            symbols.insert(label.trim().to_string(), (bank, addr));
+        }
+        Ok(SymbolFile { symbols })
+    }
+
+    pub fn get(&self, label: &str) -> Option<(usize, u16)> {
+        self.symbols.get(label).copied()
+    }
+
+    /// Find every address in `fallback` that has a label in the symbol file. Returns the new
+    /// addresses, along with the labels that could not be found which keep the address from
+    /// `fallback`.
+    pub fn resolve(&self, fallback: &Offsets) -> (Offsets, Vec<&'static str>) {
+        let mut offsets = fallback.clone();
+        let mut missing = vec![];
+        for symbol in SYMBOLS {
+            let (bank, addr) = match self.get(symbol.label) {
+                Some(location) => location,
+                None => {
+                    if !missing.contains(&symbol.label) {
+                        missing.push(symbol.label);
+                    }
+                    continue;
+                }
+            };
+            *(symbol.addr)(&mut offsets) = addr.wrapping_add_signed(symbol.offset);
+            if let Some(bank_field) = symbol.bank {
+                *bank_field(&mut offsets) = bank;
+            }
+        }
+        (offsets, missing)
+    }
+}
+
+/// Where the addresses used for a rom came from
+pub enum Source {
+    /// The rom is a known version of the game, using the built-in table
+    Version(&'static Version),
+    /// The addresses were read from a symbol file. Labels that were missing from it use the
+    /// built-in table instead, which is only allowed for known versions of the game.
+    Symbols { missing: Vec<&'static str> },
+}
+
+#[derive(Debug)]
+pub enum LoadError {
+    UnknownRom(UnknownRom),
+    Symbols(SymbolError),
+    /// Labels that were missing from the symbol file of a rom without a built-in table to fall
+    /// back to
+    MissingSymbols(Vec<&'static str>),
+}
+
+impl Error for LoadError {}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::UnknownRom(e) => e.fmt(f),
+            LoadError::Symbols(e) => e.fmt(f),
+            LoadError::MissingSymbols(labels) => {
+                write!(f, "the symbol file is missing labels: {}", labels.join(", "))
+            }
+        }
+    }
+}
+
+/// Select the addresses to use for a rom. If `symbols` is given the addresses are read from it,
+/// which allows roms that have been modified, otherwise the rom must be a known version.
+pub fn select_for_rom(rom: &[u8], symbols: Option<&Path>) -> Result<Source, LoadError> {
+    let path = match symbols {
+        Some(path) => path,
+        None => {
+            let version = offsets::select_for_rom(rom).map_err(LoadError::UnknownRom)?;
+            return Ok(Source::Version(version));
+        }
+    };
+
+    let symbols = SymbolFile::read(path).map_err(LoadError::Symbols)?;
+    let (resolved, missing) = match offsets::detect(rom) {
+        Ok(Version { offsets: Some(offsets), .. }) => symbols.resolve(offsets),
+        _ => match symbols.resolve(&offsets::RED_BLUE) {
+            // Red and Blue's addresses would be wrong for a rom that has been modified
+            (_, missing) if !missing.is_empty() => return Err(LoadError::MissingSymbols(missing)),
+            resolved => resolved,
+        },
+    };
+
+    // The table is needed for as long as the game is running, so only leak it if it will be used
+    if offsets::is_selected() || !offsets::select(Box::leak(Box::new(resolved))) {
+        return Err(LoadError::UnknownRom(UnknownRom::AlreadySelected));
+    }
+    Ok(Source::Symbols { missing })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_symbols() {
+        let symbols = SymbolFile::parse(
+            "; File generated by rgblink\n\
+            00:d35e wCurMap\n\
+            \n\
+            0e:43de BaseStats ; a comment\n\
+            00:2920 DisplayTextID\n\
+            00:292b DisplayTextID.skipSwitchToMapBank\n",
+        )
+        .unwrap();
+        assert_eq!(symbols.get("wCurMap"), Some((0x00, 0xD35E)));
+        assert_eq!(symbols.get("BaseStats"), Some((0x0E, 0x43DE)));
+        assert_eq!(symbols.get("DisplayTextID.skipSwitchToMapBank"), Some((0x00, 0x292B)));
+        assert_eq!(symbols.get("wXCoord"), None);
+    }
+
+    #[test]
+    fn invalid_line() {
+        match SymbolFile::parse("00:d35e wCurMap\nwYCoord\n") {
+            Err(SymbolError::InvalidLine(2, text)) => assert_eq!(text, "wYCoord"),
+            _ => panic!("expected an invalid line"),
+        }
+    }
+
+    #[test]
+    fn resolve_with_fallback() {
+        let symbols = SymbolFile::parse(
+            "00:d400 wCurMap\n\
+            00:3000 DisplayTextID\n\
+            00:292b DisplayTextID.skipSwitchToMapBank\n\
+            00:0bc5 IsSpriteInFrontOfPlayer2.foundSpriteInFrontOfPlayer\n\
+            0f:4100 BaseStats\n",
+        )
+        .unwrap();
+        let (offsets, missing) = symbols.resolve(&offsets::RED_BLUE);
+
+        assert_eq!(offsets.map_id, 0xD400);
+        assert_eq!(offsets.display_text_id, 0x3000);
+        assert_eq!(offsets.display_text_id_after_init, 0x292B);
+        assert_eq!(offsets.sprite_check_exit_2, 0x0BC4);
+        assert_eq!((offsets.base_stats_bank, offsets.base_stats), (0x0F, 0x4100));
+
+        // Missing labels keep the built-in address, and are only reported once
+        assert_eq!(offsets.map_x, offsets::RED_BLUE.map_x);
+        assert!(missing.contains(&"wXCoord"));
+        assert!(!missing.contains(&"wCurMap"));
+        assert!(!missing.contains(&"DisplayTextID"));
+
+        let (_, missing) = SymbolFile::parse("").unwrap().resolve(&offsets::RED_BLUE);
+        assert_eq!(missing.iter().filter(|&&label| label == "NextTextCommand").count(), 1);
+    }
+
+    #[test]
+    fn missing_labels_for_unknown_rom() {
+        let path = std::env::temp_dir().join("pikemon_missing_labels.sym");
+        fs::write(&path, "00:d35e wCurMap\n").unwrap();
+        let result = select_for_rom(&[0; 0x8000], Some(&path));
+        fs::remove_file(&path).unwrap();
+
+        match result {
+            Err(LoadError::MissingSymbols(missing)) => {
+                assert!(missing.contains(&"wXCoord"));
+                assert!(!missing.contains(&"wCurMap"));
+            }
+            _ => panic!("expected the rom to be refused"),
+        }
+    }
+}
@@ -0,0 +1,125 @@
+//! A rom for tests that is empty apart from the entries that a test adds to it, at the addresses
+//! given by the selected offsets.
+use crate::{
+    data::BaseStats,
+    extract::Rom,
+    offsets,
+    values::{evolution, items, moves, types},
+};
+
+pub const PIKACHU: u8 = 0x54;
+pub const RAICHU: u8 = 0x55;
+pub const THUNDER_STONE: u8 = 0x21;
+pub const THUNDERSHOCK: u8 = 0x54;
+pub const GROWL: u8 = 0x2D;
+pub const THUNDER_WAVE: u8 = 0x56;
+pub const QUICK_ATTACK: u8 = 0x62;
+pub const FLASH: u8 = 0x94;
+
+/// Where the evolutions and level up moves of each added species are written, after the end of
+/// the table of pointers to them
+const EVOS_MOVES_START: u16 = 0x7400;
+
+pub struct TestRom {
+    data: Vec<u8>,
+    next_evos_moves: u16,
+}
+
+impl TestRom {
+    pub fn new() -> TestRom {
+        TestRom { data: vec![0; 0x40 * 0x4000], next_evos_moves: EVOS_MOVES_START }
+    }
+
+    /// Write `bytes` at an address in a switchable bank
+    pub fn write(&mut self, bank: usize, addr: u16, bytes: &[u8]) {
+        let start = bank * 0x4000 + (addr & 0x3FFF) as usize;
+        self.data[start..start + bytes.len()].copy_from_slice(bytes);
+    }
+
+    /// Add a species with its pokedex number and base stats entry. `evolutions` is the species'
+    /// list of evolutions without the `END` marker, and `level_moves` are (level, move) pairs.
+    pub fn add_species(
+        &mut self,
+        species: u8,
+        dex_number: u8,
+        base: &BaseStats,
+        evolutions: &[u8],
+        level_moves: &[(u8, u8)],
+    ) {
+        let offsets = offsets::get();
+        let dex_order = offsets.pokedex_order + species as u16 - 1;
+        self.write(offsets.pokedex_order_bank, dex_order, &[dex_number]);
+
+        let addr = offsets.base_stats + (dex_number as u16 - 1) * offsets::BASE_STATS_SIZE;
+        let stats = [base.hp, base.attack, base.defense, base.speed, base.special];
+        self.write(offsets.base_stats_bank, addr, &[dex_number]);
+        self.write(offsets.base_stats_bank, addr + 1, &stats);
+        self.write(offsets.base_stats_bank, addr + 6, &[base.type1, base.type2, base.catch_rate]);
+        self.write(offsets.base_stats_bank, addr + 15, &base.starting_moves);
+        self.write(offsets.base_stats_bank, addr + 20, &base.machines);
+
+        let mut entry = evolutions.to_vec();
+        entry.push(0);
+        entry.extend(level_moves.iter().flat_map(|&(level, move_id)| [level, move_id]));
+        entry.push(0);
+
+        let addr = self.next_evos_moves;
+        let pointer = offsets.evos_moves_pointers + (species as u16 - 1) * 2;
+        self.write(offsets.evos_moves_bank, pointer, &addr.to_le_bytes());
+        self.write(offsets.evos_moves_bank, addr, &entry);
+        self.next_evos_moves += entry.len() as u16;
+    }
+
+    /// Set the move taught by a TM or HM, `machine` counts from 0 starting with TM01
+    pub fn set_machine(&mut self, machine: u8, move_id: u8) {
+        let offsets = offsets::get();
+        let addr = offsets.technical_machines + machine as u16;
+        self.write(offsets.technical_machines_bank, addr, &[move_id]);
+    }
+
+    /// Add Pikachu, which learns Thunder Wave at level 9 and Quick Attack at level 16, can be
+    /// taught TM01 and HM05, and evolves into Raichu with a Thunder Stone
+    pub fn add_pikachu(&mut self) {
+        let mut base = base_stats([35, 55, 30, 90, 50], types::ELECTRIC, types::ELECTRIC);
+        base.starting_moves = [THUNDERSHOCK, GROWL, moves::NONE, moves::NONE];
+        base.machines = [0x01, 0, 0, 0, 0, 0, 0x40];
+
+        let evolutions = [evolution::ITEM, THUNDER_STONE, 1, RAICHU];
+        let level_moves = [(9, THUNDER_WAVE), (16, QUICK_ATTACK)];
+        self.add_species(PIKACHU, 25, &base, &evolutions, &level_moves);
+        self.set_machine(0, moves::MEGA_PUNCH);
+        self.set_machine(items::NUM_TMS + 4, FLASH);
+    }
+
+    /// Add Raichu, which does not learn any moves by levelling up
+    pub fn add_raichu(&mut self) {
+        let mut base = base_stats([60, 90, 55, 100, 90], types::ELECTRIC, types::ELECTRIC);
+        base.starting_moves = [THUNDERSHOCK, GROWL, THUNDER_WAVE, moves::NONE];
+        self.add_species(RAICHU, 26, &base, &[], &[]);
+    }
+}
+
+impl Rom for TestRom {
+    fn rom_byte(&self, bank: usize, addr: u16) -> u8 {
+        self.data[..].rom_byte(bank, addr)
+    }
+}
+
+/// Base stats with the given stats and types, and no moves
+pub fn base_stats(stats: [u8; 5], type1: u8, type2: u8) -> BaseStats {
+    let [hp, attack, defense, speed, special] = stats;
+    BaseStats {
+        hp,
+        attack,
+        defense,
+        speed,
+        special,
+        type1,
+        type2,
+        catch_rate: 0,
+        base_exp: 0,
+        starting_moves: [moves::NONE; 4],
+        growth_rate: 0,
+        machines: [0; 7],
+    }
+}
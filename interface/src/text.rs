@@ -1,3 +1,5 @@
+use std::mem;
+
 pub mod special {
     /// Start a text section
     pub const TEXT_START: u8 = 0x00;
@@ -27,10 +29,44 @@ pub mod special {
     pub const TERMINATOR: u8 = 0x50;
 }
 
+/// The number of characters that fit on a line of the dialogue box
+pub const LINE_WIDTH: usize = 18;
+
+/// Characters that are written with a single tile but stand for more than one character. Encoding
+/// checks for these before encoding a single character.
+const TOKENS: &[(&str, u8)] = &[
+    ("<PKMN>", 0x4A),
+    ("<DOT>", 0xF2),
+    ("<PK>", 0xE1),
+    ("<MN>", 0xE2),
+    ("'d", 0xBB),
+    ("'l", 0xBC),
+    ("'s", 0xBD),
+    ("'t", 0xBE),
+    ("'v", 0xBF),
+    ("'r", 0xE4),
+    ("'m", 0xE5),
+];
+
 pub fn encode_char(char_: char) -> u8 {
-    let val = char_ as u8;
     match char_ {
-        'A'..='Z' => 0x80 + (val - 'A' as u8),
+        'A'..='Z' => 0x80 + (char_ as u32 - 'A' as u32) as u8,
+        'a'..='z' => 0xA0 + (char_ as u32 - 'a' as u32) as u8,
+        '0'..='9' => 0xF6 + (char_ as u32 - '0' as u32) as u8,
+
+        '‘' => 0x70,
+        '’' => 0x71,
+        '“' => 0x72,
+        '”' => 0x73,
+        '・' => 0x74,
+        '…' => 0x75,
+
+        '┌' => 0x79,
+        '─' => 0x7A,
+        '┐' => 0x7B,
+        '│' => 0x7C,
+        '└' => 0x7D,
+        '┘' => 0x7E,
 
         '(' => 0x9A,
         ')' => 0x9B,
@@ -39,18 +75,22 @@ pub fn encode_char(char_: char) -> u8 {
         '[' => 0x9E,
         ']' => 0x9F,
 
-        'a'..='z' => 0xA0 + (val - 'a' as u8),
+        'é' => 0xBA,
 
-        '\'' => 0xE0, // TODO: We might want to handle this case more carefully
+        '\'' => 0xE0,
         '-' => 0xE3,
         '?' => 0xE6,
         '!' => 0xE7,
         '.' => 0xE8,
-        '>' => 0xED,
+        '▷' => 0xEC,
+        '▶' | '>' => 0xED,
+        '▼' => 0xEE,
+        '♂' => 0xEF,
+        '¥' => 0xF0,
+        '×' => 0xF1,
         '/' => 0xF3,
         ',' => 0xF4,
-
-        '0'..='9' => 0xF6 + (val - '0' as u8),
+        '♀' => 0xF5,
 
         // Special characters
         ' ' => special::SPACE,
@@ -59,6 +99,124 @@ pub fn encode_char(char_: char) -> u8 {
     }
 }
 
+/// The inverse of `encode_char`, values that are not characters are decoded as `?`
+pub fn decode_char(value: u8) -> char {
+    match value {
+        0x80..=0x99 => (b'A' + (value - 0x80)) as char,
+        0xA0..=0xB9 => (b'a' + (value - 0xA0)) as char,
+        0xF6..=0xFF => (b'0' + (value - 0xF6)) as char,
+
+        0x70 => '‘',
+        0x71 => '’',
+        0x72 => '“',
+        0x73 => '”',
+        0x74 => '・',
+        0x75 => '…',
+
+        0x79 => '┌',
+        0x7A => '─',
+        0x7B => '┐',
+        0x7C => '│',
+        0x7D => '└',
+        0x7E => '┘',
+
+        0x9A => '(',
+        0x9B => ')',
+        0x9C => ':',
+        0x9D => ';',
+        0x9E => '[',
+        0x9F => ']',
+
+        0xBA => 'é',
+
+        0xE0 => '\'',
+        0xE3 => '-',
+        0xE7 => '!',
+        0xE8 => '.',
+        0xEC => '▷',
+        0xED => '▶',
+        0xEE => '▼',
+        0xEF => '♂',
+        0xF0 => '¥',
+        0xF1 => '×',
+        0xF3 => '/',
+        0xF4 => ',',
+        0xF5 => '♀',
+
+        special::SPACE => ' ',
+        special::LINE_DOWN => '\n',
+        _ => '?',
+    }
+}
+
+/// Decode text from the game, stopping at the terminator if there is one
+pub fn decode(text: &[u8]) -> String {
+    let mut decoded = String::new();
+    for &value in text.iter().take_while(|&&c| c != special::TERMINATOR) {
+        match TOKENS.iter().find(|&&(_, token_value)| token_value == value) {
+            Some((token, _)) => decoded.push_str(token),
+            None => decoded.push(decode_char(value)),
+        }
+    }
+    decoded
+}
+
+/// Encode text for the dialogue box, wrapping it to fit the width of the box. A single `\n` starts
+/// a new line and a blank line starts a new paragraph, which clears the box. The box only has room
+/// for two lines, so any further lines scroll the text up once the player presses a button.
+pub fn layout(input: &str) -> Vec<u8> {
+    let mut output = vec![];
+    for (i, paragraph) in input.split("\n\n").enumerate() {
+        if i != 0 {
+            output.push(special::PARAGRAPH);
+        }
+
+        let mut lines = vec![];
+        for line in paragraph.split('\n') {
+            wrap_line(line, &mut lines);
+        }
+
+        for (j, line) in lines.into_iter().enumerate() {
+            match j {
+                0 => {}
+                1 => output.push(special::BOTTOM_LINE),
+                _ => output.push(special::SCROLL_LINE),
+            }
+            output.extend(line);
+        }
+    }
+    output
+}
+
+/// Split a line of text into encoded lines that fit in the dialogue box, breaking it between words
+/// where possible
+fn wrap_line(input: &str, lines: &mut Vec<Vec<u8>>) {
+    let mut line = vec![];
+    for word in input.split(' ').filter(|word| !word.is_empty()) {
+        let mut word: Vec<u8> = Encoder::new(word).collect();
+
+        // Words that do not fit on a line of their own are split over multiple lines
+        while word.len() > LINE_WIDTH {
+            if !line.is_empty() {
+                lines.push(mem::take(&mut line));
+            }
+            lines.push(word.drain(..LINE_WIDTH).collect());
+        }
+        if word.is_empty() {
+            continue;
+        }
+
+        if !line.is_empty() && line.len() + 1 + word.len() > LINE_WIDTH {
+            lines.push(mem::take(&mut line));
+        }
+        if !line.is_empty() {
+            line.push(special::SPACE);
+        }
+        line.extend(word);
+    }
+    lines.push(line);
+}
+
 pub struct Encoder<'a> {
     base: &'a str,
 }
@@ -73,6 +231,12 @@ impl<'a> Iterator for Encoder<'a> {
     type Item = u8;
 
     fn next(&mut self) -> Option<u8> {
+        let token = TOKENS.iter().find(|(token, _)| self.base.starts_with(token));
+        if let Some(&(token, value)) = token {
+            self.base = &self.base[token.len()..];
+            return Some(value);
+        }
+
         if let Some((char_, rest)) = slice_shift_char(self.base) {
             self.base = rest;
             return Some(encode_char(char_));
@@ -96,3 +260,71 @@ pub fn slice_shift_char(s: &str) -> Option<(char, &str)> {
         Some((ch, &s[ch.len_utf8()..]))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode(text: &str) -> Vec<u8> {
+        Encoder::new(text).collect()
+    }
+
+    #[test]
+    fn round_trip_every_character() {
+        let text = "ABCDEFGHIJKLMNOPQRSTUVWXYZ abcdefghijklmnopqrstuvwxyz 0123456789\n\
+            ():;[]é'-?!./,‘’“”・…┌─┐│└┘▷▶▼♂♀¥×";
+        assert_eq!(decode(&encode(text)), text);
+    }
+
+    #[test]
+    fn round_trip_tokens() {
+        let text = "<PKMN> <PK><MN> 1<DOT>5 RED's I'd it'll they've we're I'm can't";
+        let encoded = encode(text);
+        assert_eq!(decode(&encoded), text);
+        assert_eq!(&encoded[..4], &[0x4A, special::SPACE, 0xE1, 0xE2]);
+        assert!(encoded.contains(&0xBD));
+    }
+
+    #[test]
+    fn round_trip_every_value() {
+        // Every value that decodes to something other than `?` must encode back to itself
+        for value in 0..=u8::MAX {
+            let decoded = decode(&[value]);
+            if value != special::TERMINATOR && decoded != "?" {
+                assert_eq!(encode(&decoded), vec![value], "{:#04X} decoded as {}", value, decoded);
+            }
+        }
+    }
+
+    #[test]
+    fn decode_stops_at_terminator() {
+        assert_eq!(decode(&[0x91, 0x84, 0x83, special::TERMINATOR, 0x80]), "RED");
+    }
+
+    #[test]
+    fn layout_wraps_words() {
+        let mut expected = encode("The quick brown");
+        expected.push(special::BOTTOM_LINE);
+        expected.extend(encode("fox jumps over the"));
+        expected.push(special::SCROLL_LINE);
+        expected.extend(encode("lazy dog."));
+        assert_eq!(layout("The quick brown fox jumps over the lazy dog."), expected);
+    }
+
+    #[test]
+    fn layout_paragraphs_and_long_words() {
+        let mut expected = encode("Hello!");
+        expected.push(special::PARAGRAPH);
+        expected.extend(encode("ABCDEFGHIJKLMNOPQR"));
+        expected.push(special::BOTTOM_LINE);
+        expected.extend(encode("ST"));
+        assert_eq!(layout("Hello!\n\nABCDEFGHIJKLMNOPQRST"), expected);
+    }
+
+    #[test]
+    fn unknown_characters() {
+        assert_eq!(encode("ü~"), vec![0xE6, 0xE6]);
+        assert_eq!(decode(&[special::PARAGRAPH]), "?");
+        assert_eq!(encode(">"), encode("▶"));
+    }
+}
@@ -8,6 +8,9 @@ pub const TRAINER_TAG: u8 = 0xC8;
 /// sprites.
 pub const MAX_MAP_TILE: u8 = 0x5F;
 
+/// The number of map ids used by the game, including unused maps
+pub const NUM_MAPS: u8 = 0xF8;
+
 #[derive(Copy, Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 pub enum Direction {
     Down = 0x0,
@@ -40,14 +43,77 @@ pub enum ActiveBattle {
     Trainer = 2,
 }
 
+/// The values of the game's link state that we use
+pub enum LinkState {
+    None = 0,
+    Battling = 4,
+}
+
+/// Bits of the serial control register
+pub mod serial {
+    /// Set by the game to start a transfer, and cleared once the transfer has finished
+    pub const START_TRANSFER: u8 = 0x80;
+    /// Set if this side of the link cable drives the clock
+    pub const INTERNAL_CLOCK: u8 = 0x01;
+    /// The bit in the interrupt flags register used to tell the game that a transfer finished
+    pub const INTERRUPT: u8 = 0x08;
+    /// What is received when nothing is on the other end of the link cable
+    pub const NO_DATA: u8 = 0xFF;
+}
+
+/// Which side of the link cable is driving the serial clock. The game uses this to break ties
+/// (e.g. speed ties) in the same way on both sides of a link battle.
+pub enum SerialClock {
+    External = 1,
+    Internal = 2,
+}
+
+/// Special values used when exchanging actions during a link battle. Values from 0 to 3 are the
+/// index of the chosen move, and values from 4 to 9 switch to the party member at (value - 4).
+pub mod link_battle {
+    pub const NO_ACTION: u8 = 0x0D;
+    pub const STRUGGLE: u8 = 0x0E;
+    pub const RUN: u8 = 0x0F;
+
+    /// The game clears the receive buffer to this value while it waits for the other player
+    pub const NO_DATA: u8 = 0xFF;
+    /// Added to the action when it is sent over the link cable, so the receiver can identify it
+    pub const NYBBLE_PREAMBLE: u8 = 0x60;
+
+    /// The number of random numbers shared by both players at the start of a battle
+    pub const NUM_RANDOM_NUMBERS: usize = 10;
+}
+
 pub enum TrainerClass {
     Unknown = 0x00,
     ProfOak = 0x1A,
 }
 
+/// The kinds of entries in a species' evolution list
+pub mod evolution {
+    pub const END: u8 = 0;
+    pub const LEVEL: u8 = 1;
+    pub const ITEM: u8 = 2;
+    pub const TRADE: u8 = 3;
+}
+
+/// The number of internal species ids, including unused ids
+pub const NUM_SPECIES: u8 = 190;
+
+/// The highest level that a pokemon can reach
+pub const MAX_LEVEL: u8 = 100;
+
+/// The number of moves, not including the empty move
+pub const NUM_MOVES: u8 = 165;
+
+/// The number of trainer classes, starting from 1
+pub const NUM_TRAINER_CLASSES: u8 = 47;
+
+/// Species ids used by the code. The names of every species can be read from the rom with
+/// `extract::name_tables`.
 pub mod pokeid {
-    // TODO: Add more pokemon
-    pub const RYHDON: u8 = 0x01;
+    pub const RHYDON: u8 = 0x01;
+    pub const MEW: u8 = 0x15;
     pub const WEEDLE: u8 = 0x70;
 }
 
@@ -61,6 +127,9 @@ pub mod status {
 }
 
 pub mod types {
+    /// Type ids between the physical and special types that the game does not use
+    pub const UNUSED: std::ops::Range<u8> = 0x09..0x14;
+
     pub const NORMAL: u8 = 0x00;
     pub const FIGHTING: u8 = 0x01;
     pub const FLYING: u8 = 0x02;
@@ -78,8 +147,9 @@ pub mod types {
     pub const DRAGON: u8 = 0x1A;
 }
 
+/// Move ids used by the code. The names of every move can be read from the rom with
+/// `extract::name_tables`.
 pub mod moves {
-    // TODO: Add more moves
     pub const NONE: u8 = 0x00;
     pub const POUND: u8 = 0x01;
     pub const KARATE_CHOP: u8 = 0x02;
@@ -97,3 +167,13 @@ pub mod moves {
     pub const SWORDS_DANCE: u8 = 0x0e;
     pub const CUT: u8 = 0x0f;
 }
+
+pub mod items {
+    /// The number of items with names stored in the rom, starting from 1
+    pub const COUNT: u8 = 0x53;
+
+    pub const FIRST_HM: u8 = 0xC4;
+    pub const NUM_HMS: u8 = 5;
+    pub const FIRST_TM: u8 = 0xC9;
+    pub const NUM_TMS: u8 = 50;
+}
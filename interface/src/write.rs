@@ -0,0 +1,67 @@
+//! Functions for writing data back into the game's memory, the inverse of `extract`
+use gb_emu::mmu::Memory;
+
+use crate::{
+    data::{PokemonData, PokemonRecord},
+    offsets, text,
+};
+
+fn pokemon_data(mem: &mut Memory, addr: u16, data: &PokemonData) {
+    mem.sb(addr, data.species);
+    big_endian_word(mem, addr + 1, data.hp);
+    mem.sb(addr + 3, data.unknown);
+    mem.sb(addr + 4, data.status);
+    mem.sb(addr + 5, data.type1);
+    mem.sb(addr + 6, data.type2);
+    mem.sb(addr + 7, data.catch_rate);
+    bytes(mem, addr + 8, &[data.moves.0, data.moves.1, data.moves.2, data.moves.3]);
+    big_endian_word(mem, addr + 12, data.ot_id);
+
+    bytes(mem, addr + 14, &[data.exp.0, data.exp.1, data.exp.2]);
+    big_endian_word(mem, addr + 17, data.hp_ev);
+    big_endian_word(mem, addr + 19, data.attack_ev);
+    big_endian_word(mem, addr + 21, data.defense_ev);
+    big_endian_word(mem, addr + 23, data.speed_ev);
+    big_endian_word(mem, addr + 25, data.special_ev);
+    bytes(mem, addr + 27, &[data.individual_values.0, data.individual_values.1]);
+    bytes(mem, addr + 29, &[data.move_pp.0, data.move_pp.1, data.move_pp.2, data.move_pp.3]);
+
+    mem.sb(addr + 33, data.level);
+    big_endian_word(mem, addr + 34, data.max_hp);
+    big_endian_word(mem, addr + 36, data.attack);
+    big_endian_word(mem, addr + 38, data.defense);
+    big_endian_word(mem, addr + 40, data.speed);
+    big_endian_word(mem, addr + 42, data.special);
+}
+
+fn big_endian_word(mem: &mut Memory, addr: u16, value: u16) {
+    mem.sb(addr, (value >> 8) as u8);
+    mem.sb(addr + 1, value as u8);
+}
+
+fn bytes(mem: &mut Memory, addr: u16, values: &[u8]) {
+    for (i, &val) in values.iter().enumerate() {
+        mem.sb(addr + i as u16, val);
+    }
+}
+
+/// Write a name, padding it with `TERMINATOR` to fill `length` bytes
+fn name(mem: &mut Memory, addr: u16, length: u16, name: &[u8]) {
+    let name = name.iter().copied().take(length as usize - 1);
+    let padding = std::iter::repeat(text::special::TERMINATOR);
+    for (i, val) in name.chain(padding).take(length as usize).enumerate() {
+        mem.sb(addr + i as u16, val);
+    }
+}
+
+/// Replace the pokemon in an existing party slot
+pub fn party_pokemon(mem: &mut Memory, slot: u8, record: &PokemonRecord) {
+    let slot = slot as u16;
+    let name_offset = slot * offsets::NAME_LENGTH;
+
+    // The party keeps a separate list of species, which is what most menus read from
+    mem.sb(offsets::get().party_species + slot, record.data.species);
+    pokemon_data(mem, offsets::get().party_poke_1 + slot * offsets::PARTY_POKE_SIZE, &record.data);
+    name(mem, offsets::get().party_ot_names + name_offset, offsets::NAME_LENGTH, &record.ot_name);
+    name(mem, offsets::get().party_nicknames + name_offset, offsets::NAME_LENGTH, &record.nickname);
+}
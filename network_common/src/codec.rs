@@ -0,0 +1,189 @@
+//! Length-prefixed binary framing for network events.
+//!
+//! Every connection starts with both sides sending a fixed size hello message containing a magic
+//! number and the protocol version. After the hello has been exchanged, each event is sent as a
+//! big-endian `u32` length followed by the bincode encoded event.
+use std::io::{Read, Write};
+
+use crate::{
+    error::{NetworkError, NetworkResult},
+    NetworkEvent,
+};
+
+/// The version of the network protocol. This must be incremented whenever `NetworkEvent` (or any
+/// of the types it contains) changes in an incompatible way.
+pub const PROTOCOL_VERSION: u16 = 17;
+
+/// Identifies a connection as speaking the Pikemon protocol
+const MAGIC: [u8; 4] = *b"PKMN";
+
+/// The largest frame we are willing to accept. Frames larger than this are treated as corrupt data
+/// instead of attempting to allocate a huge buffer.
+pub const MAX_FRAME_SIZE: usize = 64 * 1024;
+
+/// Write our hello message to the stream
+pub fn write_hello<W: Write>(writer: &mut W) -> NetworkResult<()> {
+    let mut hello = [0; 6];
+    hello[..4].copy_from_slice(&MAGIC);
+    hello[4..].copy_from_slice(&PROTOCOL_VERSION.to_be_bytes());
+    writer.write_all(&hello)?;
+    Ok(())
+}
+
+/// Read the hello message from the other side of the stream, and check that it is compatible with
+/// our version of the protocol.
+pub fn read_hello<R: Read>(reader: &mut R) -> NetworkResult<()> {
+    let mut hello = [0; 6];
+    reader.read_exact(&mut hello)?;
+    if hello[..4] != MAGIC {
+        return Err(NetworkError::DecodeError);
+    }
+
+    let version = u16::from_be_bytes([hello[4], hello[5]]);
+    if version != PROTOCOL_VERSION {
+        return Err(NetworkError::VersionMismatch(PROTOCOL_VERSION, version));
+    }
+    Ok(())
+}
+
+/// Exchange hello messages with the other side of the stream. Both the client and the server send
+/// their hello before reading, so the order that the two sides call this in does not matter.
+pub fn handshake<S: Read + Write>(stream: &mut S) -> NetworkResult<()> {
+    write_hello(stream)?;
+    stream.flush()?;
+    read_hello(stream)
+}
+
+/// Encode an event and write it to the stream as a single frame
+pub fn write_event<W: Write>(writer: &mut W, event: &NetworkEvent) -> NetworkResult<()> {
+    let payload = bincode::serialize(event).map_err(|_| NetworkError::EncodeError)?;
+    if payload.len() > MAX_FRAME_SIZE {
+        return Err(NetworkError::EncodeError);
+    }
+
+    // Build the entire frame before writing it, so that the length and the payload are sent
+    // together.
+    let mut frame = Vec::with_capacity(4 + payload.len());
+    frame.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    frame.extend_from_slice(&payload);
+    writer.write_all(&frame)?;
+    Ok(())
+}
+
+/// Read a single frame from the stream and decode the event inside of it
+pub fn read_event<R: Read>(reader: &mut R) -> NetworkResult<NetworkEvent> {
+    let mut length = [0; 4];
+    reader.read_exact(&mut length)?;
+
+    let length = u32::from_be_bytes(length) as usize;
+    if length > MAX_FRAME_SIZE {
+        return Err(NetworkError::DecodeError);
+    }
+
+    let mut payload = vec![0; length];
+    reader.read_exact(&mut payload)?;
+    bincode::deserialize(&payload).map_err(|_| NetworkError::DecodeError)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Cursor};
+
+    use super::*;
+
+    /// A stream that reads from `input` and writes to `output`, like one end of a connection
+    struct Duplex {
+        input: Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl Read for Duplex {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for Duplex {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn hello(magic: [u8; 4], version: u16) -> Vec<u8> {
+        let mut hello = magic.to_vec();
+        hello.extend_from_slice(&version.to_be_bytes());
+        hello
+    }
+
+    #[test]
+    fn handshake_with_same_version() {
+        let mut stream =
+            Duplex { input: Cursor::new(hello(MAGIC, PROTOCOL_VERSION)), output: vec![] };
+        handshake(&mut stream).unwrap();
+        assert_eq!(stream.output, hello(MAGIC, PROTOCOL_VERSION));
+    }
+
+    #[test]
+    fn handshake_version_mismatch() {
+        let remote = PROTOCOL_VERSION + 1;
+        let mut stream = Duplex { input: Cursor::new(hello(MAGIC, remote)), output: vec![] };
+        match handshake(&mut stream) {
+            Err(NetworkError::VersionMismatch(local, version)) => {
+                assert_eq!((local, version), (PROTOCOL_VERSION, remote))
+            }
+            other => panic!("expected a version mismatch, got {:?}", other),
+        }
+        // Our hello is still sent so that the other side can report the mismatch too
+        assert_eq!(stream.output, hello(MAGIC, PROTOCOL_VERSION));
+    }
+
+    #[test]
+    fn handshake_wrong_magic() {
+        let mut stream =
+            Duplex { input: Cursor::new(hello(*b"HTTP", PROTOCOL_VERSION)), output: vec![] };
+        assert!(matches!(handshake(&mut stream), Err(NetworkError::DecodeError)));
+    }
+
+    #[test]
+    fn handshake_truncated() {
+        let mut stream = Duplex { input: Cursor::new(MAGIC.to_vec()), output: vec![] };
+        assert!(matches!(handshake(&mut stream), Err(NetworkError::Io(_))));
+    }
+
+    #[test]
+    fn event_round_trip() {
+        let mut buffer = vec![];
+        write_event(&mut buffer, &NetworkEvent::ServerMessage("Hello".into())).unwrap();
+        write_event(&mut buffer, &NetworkEvent::PlayerQuit(7)).unwrap();
+
+        let mut reader = Cursor::new(buffer);
+        match read_event(&mut reader).unwrap() {
+            NetworkEvent::ServerMessage(message) => assert_eq!(message, "Hello"),
+            _ => panic!("expected a server message"),
+        }
+        assert!(matches!(read_event(&mut reader).unwrap(), NetworkEvent::PlayerQuit(7)));
+        assert!(matches!(read_event(&mut reader), Err(NetworkError::Io(_))));
+    }
+
+    #[test]
+    fn oversized_frame_rejected() {
+        // The length is checked before anything is allocated or read
+        let frame = ((MAX_FRAME_SIZE + 1) as u32).to_be_bytes();
+        assert!(matches!(read_event(&mut Cursor::new(frame)), Err(NetworkError::DecodeError)));
+
+        let message = "A".repeat(MAX_FRAME_SIZE + 1);
+        let result = write_event(&mut vec![], &NetworkEvent::ServerMessage(message));
+        assert!(matches!(result, Err(NetworkError::EncodeError)));
+    }
+
+    #[test]
+    fn invalid_payload_rejected() {
+        let mut frame = 2u32.to_be_bytes().to_vec();
+        frame.extend_from_slice(&[0xFF, 0xFF]);
+        assert!(matches!(read_event(&mut Cursor::new(frame)), Err(NetworkError::DecodeError)));
+    }
+}
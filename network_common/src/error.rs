@@ -10,6 +10,8 @@ pub enum NetworkError {
     RecvError,
     DecodeError,
     EncodeError,
+    VersionMismatch(u16, u16),
+    Rejected(String),
 }
 
 impl Error for NetworkError {}
@@ -22,6 +24,12 @@ impl fmt::Display for NetworkError {
             NetworkError::RecvError => f.write_str("receiving on a closed channel"),
             NetworkError::DecodeError => f.write_str("received invalid network data"),
             NetworkError::EncodeError => f.write_str("failed to encode network data"),
+            NetworkError::VersionMismatch(local, remote) => write!(
+                f,
+                "incompatible protocol version (local: {}, remote: {})",
+                local, remote
+            ),
+            NetworkError::Rejected(reason) => write!(f, "rejected by server: {}", reason),
         }
     }
 }
@@ -1,18 +1,141 @@
-use interface::data::{PlayerData, MovementData, BattleData};
+use std::time::Duration;
 
+use interface::data::{PlayerData, MovementData, BattleData, Party, PokemonRecord};
+
+pub mod codec;
 pub mod error;
 
 pub type PlayerId = u32;
 
+/// A secret issued to each player when they join, which can be presented when reconnecting to
+/// resume the same session (and therefore keep the same `PlayerId`).
+pub type ResumeToken = u64;
+
+/// A secret generated by each client the first time it runs, used to identify the player's save
+/// file on the server
+pub type AccountKey = u64;
+
+/// The port that the server listens on when no other port is configured
+pub const DEFAULT_PORT: u16 = 8080;
+
+/// How often a heartbeat is sent on an otherwise idle connection
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How long to wait without receiving anything before treating a connection as dead
+pub const IDLE_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// How long a single write is allowed to block before treating a connection as dead
+pub const WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long the server keeps the session of a disconnected player around, so that they can resume
+/// it when they reconnect
+pub const RESUME_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Gameplay rules set by the server, which every client is expected to follow
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct ServerRules {
+    /// Whether players are allowed to speed up their game
+    pub allow_fast_mode: bool,
+    /// Whether players are allowed to battle each other
+    pub allow_battles: bool,
+    /// Whether players are allowed to trade with each other
+    pub allow_trades: bool,
+    /// How long a player waits for another player to answer a request before giving up
+    pub request_timeout_secs: u64,
+}
+
+impl Default for ServerRules {
+    fn default() -> ServerRules {
+        ServerRules {
+            allow_fast_mode: true,
+            allow_battles: true,
+            allow_trades: true,
+            request_timeout_secs: 30,
+        }
+    }
+}
+
+/// A save file stored on the server. The counter is increased every time the game is saved, so that
+/// the server can tell whether an upload is based on the save that it has.
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub struct RemoteSave {
+    pub counter: u64,
+    pub data: Vec<u8>,
+}
+
 #[derive(Clone, serde::Serialize, serde::Deserialize)]
 pub enum NetworkEvent {
     FullUpdate(PlayerId, PlayerData),
     MovementUpdate(PlayerId, MovementData),
-    UpdateRequest,
-    PlayerJoin(PlayerId),
+    /// Tells the server which maps are connected to a map, sent by clients when they enter a map
+    MapConnections(u8, Vec<u8>),
+    /// The first event sent by a client after the handshake, optionally resuming a previous session
+    JoinRequest(Option<ResumeToken>),
+    PlayerJoin(PlayerId, ResumeToken),
+    /// Sent instead of `PlayerJoin` when the server refuses to let a client join
+    JoinRejected(String),
+    Rules(ServerRules),
+    ServerMessage(String),
     PlayerQuit(PlayerId),
     Chat(PlayerId, String),
-    BattleDataRequest(PlayerId, PlayerId),
-    BattleDataResponse(PlayerId, BattleData),
+    /// A chat message that only one player sees: (to, from, message)
+    DirectChat(PlayerId, PlayerId, String),
+    /// Challenge another player to a battle: (challenged, challenger)
+    BattleChallenge(PlayerId, PlayerId),
+    /// Accept a challenge, followed by a `BattleDataResponse`: (challenger, challenged)
+    BattleAccept(PlayerId, PlayerId),
+    /// Decline a challenge: (challenger, challenged)
+    BattleDecline(PlayerId, PlayerId),
+    /// The party of the player that accepted a challenge: (challenger, challenged, party)
+    BattleDataResponse(PlayerId, PlayerId, BattleData),
+    /// Sent by the challenger once the challenge has been accepted to start the battle:
+    /// (challenged, challenger, challenger's party, shared random numbers)
+    BattleStart(PlayerId, PlayerId, BattleData, Vec<u8>),
+    /// The action chosen by a player for the current turn of a battle: (opponent, player, action)
+    BattleAction(PlayerId, PlayerId, u8),
+    /// Withdraw a challenge that has not been answered yet: (challenged, challenger)
+    BattleCancel(PlayerId, PlayerId),
+    /// Sent by the server to both players when a battle cannot go ahead because one of the teams
+    /// could not have been obtained in the game: (opponent, reason)
+    BattleRejected(PlayerId, String),
+    /// Offer the pokemon in a party slot for a trade: (partner, player, slot, pokemon)
+    TradeOffer(PlayerId, PlayerId, u8, PokemonRecord),
+    /// Agree to trade the offered pokemon: (partner, player)
+    TradeConfirm(PlayerId, PlayerId),
+    /// Back out of a trade: (partner, player)
+    TradeCancel(PlayerId, PlayerId),
+    /// Sent by the server once both players have confirmed a trade: (partner, slot, pokemon)
+    TradeComplete(PlayerId, u8, PokemonRecord),
+    /// Ask to see another player's party: (player, requester)
+    PartyRequest(PlayerId, PlayerId),
+    /// The answer to a `PartyRequest`: (requester, player, party)
+    PartyResponse(PlayerId, PlayerId, Party),
+    /// Ask the server to connect our link cable to another player on the same map, sent when the
+    /// game first tries to use the link cable
+    LinkRequest,
+    /// Sent by the server to both players once their link cables have been connected
+    LinkOpened(PlayerId),
+    /// A byte sent by the player driving the link cable's clock: (partner, player, data). Bytes are
+    /// not batched, as the game chooses each byte from the one that it received before.
+    LinkTransfer(PlayerId, PlayerId, u8),
+    /// The byte sent back in answer to a `LinkTransfer`: (partner, player, data)
+    LinkReply(PlayerId, PlayerId, u8),
+    /// Unplug a player's link cable, forwarded to the player on the other end
+    LinkClose(PlayerId),
+    /// Sent instead of `JoinRequest` to fetch the save stored for an account, answered with
+    /// `SaveData`
+    SaveDownload(AccountKey),
+    SaveData(Option<RemoteSave>),
+    /// Sent instead of `JoinRequest` to store a save for an account: (account, counter of the save
+    /// that it replaces, new save). Answered with `SaveAccepted` or `SaveConflict`.
+    SaveUpload(AccountKey, u64, RemoteSave),
+    SaveAccepted(u64),
+    /// The upload was not based on the save stored on the server, contains the stored counter
+    SaveConflict(u64),
+    /// Sent back to a player that sent a request to a player that is not connected
+    PlayerUnavailable(PlayerId),
+    Heartbeat,
+    /// Sent to the client's own event queue when the connection to the server is lost
     ServerFailure,
 }
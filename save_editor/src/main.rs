@@ -0,0 +1,250 @@
+//! Command line tool for inspecting and editing save files.
+//!
+//! `dump` prints a save as TOML, and `edit` applies a TOML file in the same format to a save. Every
+//! field in an edit file is optional, so an edit file only needs to contain what should change. A
+//! field that is present replaces the whole value in the save, including every item of a list.
+use std::{error::Error, fs};
+
+use interface::{
+    data::{PokemonData, PokemonRecord},
+    save::{
+        Item, PlayTime, SaveData, BAG_CAPACITY, BOX_CAPACITY, NUM_BOXES, PARTY_CAPACITY,
+        PC_ITEM_CAPACITY, POKEDEX_LENGTH,
+    },
+    text,
+};
+
+const USAGE: &str = "\
+Usage:
+    pikemon_save dump <SAVE>                         Print a save file as TOML
+    pikemon_save edit <SAVE> <EDITS> [-o <OUTPUT>]   Apply the edits in a TOML file to a save file
+
+The edit file uses the same format as the output of `dump`, with every field being optional. Lists
+replace the whole list in the save, so `boxes` must contain all of the boxes. Without -o, the save
+file is modified in place.";
+
+/// The most money that the game can store
+const MAX_MONEY: u32 = 999999;
+
+/// A pokemon with its names as text
+#[derive(serde::Serialize, serde::Deserialize)]
+struct Pokemon {
+    nickname: String,
+    ot_name: String,
+    data: PokemonData,
+}
+
+impl Pokemon {
+    fn from_record(record: &PokemonRecord) -> Pokemon {
+        Pokemon {
+            nickname: text::decode(&record.nickname),
+            ot_name: text::decode(&record.ot_name),
+            data: record.data.clone(),
+        }
+    }
+
+    fn into_record(self) -> PokemonRecord {
+        PokemonRecord {
+            data: self.data,
+            ot_name: text::Encoder::new(&self.ot_name).collect(),
+            nickname: text::Encoder::new(&self.nickname).collect(),
+        }
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct PokemonBox {
+    pokemon: Vec<Pokemon>,
+}
+
+/// The parts of a save that can be edited
+#[derive(Default, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+struct Edits {
+    player_name: Option<String>,
+    rival_name: Option<String>,
+    player_id: Option<u16>,
+    money: Option<u32>,
+    /// One bit for each badge, e.g. 255 for all eight badges
+    badges: Option<u8>,
+    current_box: Option<u8>,
+    /// Pokedex numbers of the pokemon that have been caught and seen
+    pokedex_owned: Option<Vec<usize>>,
+    pokedex_seen: Option<Vec<usize>>,
+    play_time: Option<PlayTime>,
+    bag: Option<Vec<Item>>,
+    pc_items: Option<Vec<Item>>,
+    party: Option<Vec<Pokemon>>,
+    boxes: Option<Vec<PokemonBox>>,
+}
+
+impl Edits {
+    fn from_save(save: &SaveData) -> Edits {
+        Edits {
+            player_name: Some(text::decode(&save.player_name)),
+            rival_name: Some(text::decode(&save.rival_name)),
+            player_id: Some(save.player_id),
+            money: Some(save.money),
+            badges: Some(save.badges),
+            current_box: Some(save.current_box),
+            pokedex_owned: Some(dex_numbers(&save.pokedex_owned)),
+            pokedex_seen: Some(dex_numbers(&save.pokedex_seen)),
+            play_time: Some(save.play_time),
+            bag: Some(save.bag.clone()),
+            pc_items: Some(save.pc_items.clone()),
+            party: Some(to_pokemon(&save.party)),
+            boxes: Some(save.boxes.iter().map(|b| PokemonBox { pokemon: to_pokemon(b) }).collect()),
+        }
+    }
+
+    /// Check that every value fits in the save, as `SaveData::to_bytes` would silently cut off
+    /// anything that does not
+    fn validate(&self) -> Result<(), String> {
+        let check_len = |name: &str, len: usize, capacity: usize| {
+            if len > capacity {
+                return Err(format!("{} has {} entries, but only {} fit", name, len, capacity));
+            }
+            Ok(())
+        };
+
+        if let Some(money) = self.money.filter(|&money| money > MAX_MONEY) {
+            return Err(format!("money is {}, but the most that fits is {}", money, MAX_MONEY));
+        }
+        if let Some(current_box) = self.current_box.filter(|&i| i as usize >= NUM_BOXES) {
+            let last = NUM_BOXES - 1;
+            return Err(format!("current_box is {}, but the last box is {}", current_box, last));
+        }
+        for numbers in [&self.pokedex_owned, &self.pokedex_seen].into_iter().flatten() {
+            if let Some(number) = numbers.iter().find(|&&n| n == 0 || n > POKEDEX_LENGTH) {
+                return Err(format!("{} is not a pokedex number (1-{})", number, POKEDEX_LENGTH));
+            }
+        }
+        if let Some(bag) = &self.bag {
+            check_len("bag", bag.len(), BAG_CAPACITY)?;
+        }
+        if let Some(pc_items) = &self.pc_items {
+            check_len("pc_items", pc_items.len(), PC_ITEM_CAPACITY)?;
+        }
+        if let Some(party) = &self.party {
+            check_len("party", party.len(), PARTY_CAPACITY)?;
+        }
+        if let Some(boxes) = &self.boxes {
+            if boxes.len() != NUM_BOXES {
+                return Err(format!("boxes has {} boxes, but must have {}", boxes.len(), NUM_BOXES));
+            }
+            for (i, pokemon_box) in boxes.iter().enumerate() {
+                check_len(&format!("box {}", i), pokemon_box.pokemon.len(), BOX_CAPACITY)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn apply(self, save: &mut SaveData) {
+        let encode = |name: String| text::Encoder::new(&name).collect();
+
+        if let Some(name) = self.player_name {
+            save.player_name = encode(name);
+        }
+        if let Some(name) = self.rival_name {
+            save.rival_name = encode(name);
+        }
+        if let Some(player_id) = self.player_id {
+            save.player_id = player_id;
+        }
+        if let Some(money) = self.money {
+            save.money = money;
+        }
+        if let Some(badges) = self.badges {
+            save.badges = badges;
+        }
+        if let Some(current_box) = self.current_box {
+            save.current_box = current_box;
+        }
+        if let Some(owned) = self.pokedex_owned {
+            save.pokedex_owned = dex_flags(&owned);
+        }
+        if let Some(seen) = self.pokedex_seen {
+            save.pokedex_seen = dex_flags(&seen);
+        }
+        if let Some(play_time) = self.play_time {
+            save.play_time = play_time;
+        }
+        if let Some(bag) = self.bag {
+            save.bag = bag;
+        }
+        if let Some(pc_items) = self.pc_items {
+            save.pc_items = pc_items;
+        }
+        if let Some(party) = self.party {
+            save.party = to_records(party);
+        }
+        if let Some(boxes) = self.boxes {
+            save.boxes = boxes.into_iter().map(|b| to_records(b.pokemon)).collect();
+        }
+    }
+}
+
+fn to_pokemon(records: &[PokemonRecord]) -> Vec<Pokemon> {
+    records.iter().map(Pokemon::from_record).collect()
+}
+
+fn to_records(pokemon: Vec<Pokemon>) -> Vec<PokemonRecord> {
+    pokemon.into_iter().map(Pokemon::into_record).collect()
+}
+
+fn dex_numbers(flags: &[bool]) -> Vec<usize> {
+    flags.iter().enumerate().filter(|(_, &flag)| flag).map(|(i, _)| i + 1).collect()
+}
+
+fn dex_flags(numbers: &[usize]) -> Vec<bool> {
+    (1..=POKEDEX_LENGTH).map(|number| numbers.contains(&number)).collect()
+}
+
+fn load_save(path: &str) -> Result<SaveData, Box<dyn Error>> {
+    let data = fs::read(path).map_err(|e| format!("failed to read {}: {}", path, e))?;
+    Ok(SaveData::parse(&data).map_err(|e| format!("failed to parse {}: {}", path, e))?)
+}
+
+fn run(args: &[String]) -> Result<(), Box<dyn Error>> {
+    match args {
+        [command, save_path] if command == "dump" => {
+            let save = load_save(save_path)?;
+            // Going through `toml::Value` puts values before tables, which TOML requires, even
+            // when lists are empty
+            let value = toml::Value::try_from(Edits::from_save(&save))?;
+            print!("{}", toml::to_string(&value)?);
+        }
+
+        [command, save_path, edits_path, rest @ ..] if command == "edit" => {
+            let output_path = match rest {
+                [] => save_path,
+                [flag, output_path] if flag == "-o" || flag == "--output" => output_path,
+                _ => return Err(USAGE.into()),
+            };
+
+            let mut save = load_save(save_path)?;
+            let edits = fs::read_to_string(edits_path)
+                .map_err(|e| format!("failed to read {}: {}", edits_path, e))?;
+            let edits: Edits = toml::from_str(&edits)
+                .map_err(|e| format!("failed to parse {}: {}", edits_path, e))?;
+            edits.validate().map_err(|e| format!("invalid edits in {}: {}", edits_path, e))?;
+            edits.apply(&mut save);
+
+            fs::write(output_path, save.to_bytes())
+                .map_err(|e| format!("failed to write {}: {}", output_path, e))?;
+            println!("Saved {}", output_path);
+        }
+
+        _ => return Err(USAGE.into()),
+    }
+
+    Ok(())
+}
+
+fn main() {
+    let args: Vec<_> = std::env::args().skip(1).collect();
+    if let Err(e) = run(&args) {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
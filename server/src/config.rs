@@ -0,0 +1,193 @@
+//! Server configuration, loaded from a TOML file and overridden by command line flags.
+use std::{fmt, fs, path::Path, str::FromStr};
+
+use network_common::{ServerRules, DEFAULT_PORT};
+
+/// The config file that is loaded if no other file is specified on the command line
+const DEFAULT_CONFIG_PATH: &str = "server.toml";
+
+const USAGE: &str = "\
+Usage: pikemon_server [OPTIONS]
+
+Options:
+    --config <PATH>        Load configuration from a TOML file (default: server.toml)
+    --bind <ADDRESS>       Address to listen on
+    --port <PORT>          Port to listen on
+    --max-players <N>      Maximum number of connected players
+    --motd <MESSAGE>       Message sent to players when they join
+    --no-fast-mode         Prevent players from using fast mode
+    --no-battles           Prevent players from battling each other
+    --no-trades            Prevent players from trading with each other
+    --request-timeout <S>  Seconds to wait for other players to answer requests
+    --save-dir <PATH>      Directory to store player save files in (default: saves)
+    --rom <PATH>           The game's rom, used for map connections and checking teams
+    --party-check <MODE>   What to do with illegal teams: off, flag or reject (default: off)
+    --help                 Print this message";
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(default)]
+pub struct ServerConfig {
+    pub bind_address: String,
+    pub port: u16,
+    pub max_players: usize,
+    pub motd: String,
+    /// Where save files uploaded by players are stored
+    pub save_dir: String,
+    /// The game's rom, which map connections and the tables used to check teams are read from
+    pub rom: Option<String>,
+    pub party_check: PartyCheck,
+    pub rules: ServerRules,
+}
+
+impl Default for ServerConfig {
+    fn default() -> ServerConfig {
+        ServerConfig {
+            bind_address: "0.0.0.0".into(),
+            port: DEFAULT_PORT,
+            max_players: 32,
+            motd: String::new(),
+            save_dir: "saves".into(),
+            rom: None,
+            party_check: PartyCheck::Off,
+            rules: ServerRules::default(),
+        }
+    }
+}
+
+/// What the server does when a player brings a team that could not have been obtained in the game
+/// to a battle
+#[derive(Copy, Clone, Debug, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PartyCheck {
+    /// Teams are not checked
+    Off,
+    /// The battle goes ahead, but the opponent is warned about the team
+    Flag,
+    /// The battle is cancelled
+    Reject,
+}
+
+impl FromStr for PartyCheck {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<PartyCheck, ()> {
+        match value {
+            "off" => Ok(PartyCheck::Off),
+            "flag" => Ok(PartyCheck::Flag),
+            "reject" => Ok(PartyCheck::Reject),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Usage,
+    Io(String, std::io::Error),
+    Parse(String, toml::de::Error),
+    InvalidArgument(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Usage => f.write_str(USAGE),
+            ConfigError::Io(path, e) => write!(f, "failed to read {}: {}", path, e),
+            ConfigError::Parse(path, e) => write!(f, "failed to parse {}: {}", path, e),
+            ConfigError::InvalidArgument(msg) => write!(f, "{}\n\n{}", msg, USAGE),
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Load the server configuration from the config file (if one exists) and then apply any
+    /// overrides from the command line arguments.
+    pub fn from_args<I: Iterator<Item = String>>(args: I) -> Result<ServerConfig, ConfigError> {
+        let args: Vec<_> = args.collect();
+
+        // The config file needs to be loaded first, so that the other flags can override it
+        let config_path = match args.iter().position(|arg| arg == "--config") {
+            Some(i) => Some(value(&args, i)?.to_string()),
+            None => None,
+        };
+        let mut config = match config_path {
+            Some(path) => ServerConfig::load(&path)?,
+            None if Path::new(DEFAULT_CONFIG_PATH).exists() => {
+                ServerConfig::load(DEFAULT_CONFIG_PATH)?
+            }
+            None => ServerConfig::default(),
+        };
+
+        let mut i = 0;
+        while i < args.len() {
+            match &*args[i] {
+                "--config" => i += 1,
+                "--bind" => {
+                    config.bind_address = value(&args, i)?.to_string();
+                    i += 1;
+                }
+                "--port" => {
+                    config.port = parse_value(&args, i)?;
+                    i += 1;
+                }
+                "--max-players" => {
+                    config.max_players = parse_value(&args, i)?;
+                    i += 1;
+                }
+                "--motd" => {
+                    config.motd = value(&args, i)?.to_string();
+                    i += 1;
+                }
+                "--save-dir" => {
+                    config.save_dir = value(&args, i)?.to_string();
+                    i += 1;
+                }
+                "--rom" => {
+                    config.rom = Some(value(&args, i)?.to_string());
+                    i += 1;
+                }
+                "--party-check" => {
+                    config.party_check = parse_value(&args, i)?;
+                    i += 1;
+                }
+                "--no-fast-mode" => config.rules.allow_fast_mode = false,
+                "--no-battles" => config.rules.allow_battles = false,
+                "--no-trades" => config.rules.allow_trades = false,
+                "--request-timeout" => {
+                    config.rules.request_timeout_secs = parse_value(&args, i)?;
+                    i += 1;
+                }
+                "--help" | "-h" => return Err(ConfigError::Usage),
+                other => {
+                    return Err(ConfigError::InvalidArgument(format!("Unknown argument: {}", other)))
+                }
+            }
+            i += 1;
+        }
+
+        Ok(config)
+    }
+
+    fn load(path: &str) -> Result<ServerConfig, ConfigError> {
+        let data = fs::read_to_string(path).map_err(|e| ConfigError::Io(path.into(), e))?;
+        toml::from_str(&data).map_err(|e| ConfigError::Parse(path.into(), e))
+    }
+
+    pub fn listen_addr(&self) -> (&str, u16) {
+        (&self.bind_address, self.port)
+    }
+}
+
+/// Get the value following the flag at index `i`
+fn value(args: &[String], i: usize) -> Result<&str, ConfigError> {
+    match args.get(i + 1) {
+        Some(value) => Ok(value),
+        None => Err(ConfigError::InvalidArgument(format!("Missing value for {}", args[i]))),
+    }
+}
+
+fn parse_value<T: std::str::FromStr>(args: &[String], i: usize) -> Result<T, ConfigError> {
+    value(args, i)?.parse().map_err(|_| {
+        ConfigError::InvalidArgument(format!("Invalid value for {}: {}", args[i], args[i + 1]))
+    })
+}
@@ -0,0 +1,51 @@
+//! Interest management: decides which players need to be told about each other's movement.
+use std::collections::HashMap;
+
+use interface::{
+    data::MovementData,
+    extract::{self, Rom},
+    values::NUM_MAPS,
+};
+
+/// The most connections that a map can have (one in each direction)
+const MAX_CONNECTIONS: usize = 4;
+
+/// The connections between maps. These are read from the rom when the server has one, otherwise
+/// they are built up from what clients report as they move between maps.
+#[derive(Default)]
+pub struct MapLinks {
+    links: HashMap<u8, Vec<u8>>,
+    /// Set when the links were read from the rom, so reports from clients are not needed
+    from_rom: bool,
+}
+
+impl MapLinks {
+    pub fn from_rom<R: Rom + ?Sized>(rom: &R) -> MapLinks {
+        let links = (0..NUM_MAPS)
+            .map(|map_id| (map_id, extract::map_header(rom, map_id).connected_maps()))
+            .collect();
+        MapLinks { links, from_rom: true }
+    }
+
+    /// Record the connections reported by a client that has entered `map_id`. The first report for
+    /// each map is kept, so that a client cannot replace the connections that other players have
+    /// already reported.
+    pub fn insert(&mut self, map_id: u8, mut connected_maps: Vec<u8>) {
+        if self.from_rom || self.links.contains_key(&map_id) {
+            return;
+        }
+        connected_maps.truncate(MAX_CONNECTIONS);
+        self.links.insert(map_id, connected_maps);
+    }
+
+    /// Check if a player at `observer` is able to see a player at `target`
+    pub fn can_see(&self, observer: &MovementData, target: &MovementData) -> bool {
+        // Connections in the game always go both ways, so a connection only counts if both maps
+        // agree on it. This stops a client from linking its map to every other map.
+        let connects = |from: u8, to: u8| {
+            self.links.get(&from).is_some_and(|maps| maps.contains(&to))
+        };
+        let (a, b) = (observer.map_id, target.map_id);
+        a == b || (connects(a, b) && connects(b, a))
+    }
+}
@@ -0,0 +1,46 @@
+//! Link cables between players. A player's cable is connected to the next player that tries to use
+//! the link cable on the same map (i.e. at the same Cable Club), and stays connected until either
+//! player unplugs it or disconnects.
+use std::collections::HashMap;
+
+use interface::PlayerId;
+
+#[derive(Default)]
+pub struct LinkCables {
+    /// Players waiting for another player to connect to, by map
+    waiting: HashMap<u8, PlayerId>,
+    partners: HashMap<PlayerId, PlayerId>,
+}
+
+impl LinkCables {
+    /// Connect `player`'s link cable, returning the player on the other end if there is already
+    /// someone waiting on the same map.
+    pub fn request(&mut self, player: PlayerId, map_id: u8) -> Option<PlayerId> {
+        self.close(player);
+
+        match self.waiting.remove(&map_id) {
+            Some(partner) => {
+                self.partners.insert(player, partner);
+                self.partners.insert(partner, player);
+                Some(partner)
+            }
+            None => {
+                self.waiting.insert(map_id, player);
+                None
+            }
+        }
+    }
+
+    /// The player on the other end of `player`'s link cable
+    pub fn partner(&self, player: PlayerId) -> Option<PlayerId> {
+        self.partners.get(&player).copied()
+    }
+
+    /// Unplug `player`'s link cable, returning the player that was on the other end
+    pub fn close(&mut self, player: PlayerId) -> Option<PlayerId> {
+        self.waiting.retain(|_, waiting| *waiting != player);
+        let partner = self.partners.remove(&player)?;
+        self.partners.remove(&partner);
+        Some(partner)
+    }
+}
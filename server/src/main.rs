@@ -1,140 +1,631 @@
 use std::{
     collections::HashMap,
-    io::{self, BufRead, BufReader, Write},
-    net::{TcpListener, TcpStream},
+    fs,
+    io::BufReader,
+    net::{Shutdown, TcpListener, TcpStream},
+    path::Path,
+    sync::Arc,
     thread,
 };
 
-use interface::PlayerId;
+use interface::{
+    data::{BattleData, MovementData, PlayerData},
+    legality::Legality,
+    symbols::{self, Source},
+    text, PlayerId,
+};
 use network_common::{
+    codec,
     error::{NetworkError, NetworkResult},
-    NetworkEvent,
+    NetworkEvent, ResumeToken, HEARTBEAT_INTERVAL, IDLE_TIMEOUT, WRITE_TIMEOUT,
+};
+
+use crate::{
+    config::{PartyCheck, ServerConfig},
+    interest::MapLinks,
+    link::LinkCables,
+    session::Sessions,
+    storage::SaveStorage,
+    trade::{Received, Trades},
 };
 
+mod config;
+mod interest;
+mod link;
+mod session;
+mod storage;
+mod trade;
+
+/// Identifies a single connection to the server. A player keeps the same id when they resume
+/// their session, so this is used to ignore events from connections that have been replaced.
+type ConnectionId = u64;
+
 struct Client {
     id: PlayerId,
+    connection: ConnectionId,
+    client_stream: TcpStream,
+    server_sender: crossbeam_channel::Sender<ClientPacket>,
+}
+
+/// An event received from a client
+struct ClientPacket {
+    id: PlayerId,
+    connection: ConnectionId,
+    event: NetworkEvent,
+}
+
+/// The server's view of a connected client
+struct Connection {
+    connection: ConnectionId,
     client_stream: TcpStream,
-    server_sender: crossbeam_channel::Sender<NetworkEvent>,
+    /// The last full update received from the client, kept so that we can send it to players that
+    /// later move into view
+    player: Option<PlayerData>,
+    movement: Option<MovementData>,
+    /// The connections the client reported for the map it is entering, which are only trusted once
+    /// the client's position shows that it is on that map
+    reported_links: Option<(u8, Vec<u8>)>,
 }
 
-fn run_server(bind_addr: &str) -> NetworkResult<()> {
-    let listener = TcpListener::bind(bind_addr)?;
+struct Server {
+    config: ServerConfig,
+    clients: HashMap<PlayerId, Connection>,
+    sessions: Sessions,
+    map_links: MapLinks,
+    trades: Trades,
+    link_cables: LinkCables,
+    /// Used to check the teams that players bring to battles, if the server has been configured to
+    legality: Option<Legality>,
+    next_connection: ConnectionId,
+    packet_sender: crossbeam_channel::Sender<ClientPacket>,
+}
+
+impl Server {
+    fn new(
+        config: ServerConfig,
+        legality: Option<Legality>,
+        map_links: MapLinks,
+        packet_sender: crossbeam_channel::Sender<ClientPacket>,
+    ) -> Server {
+        Server {
+            config,
+            legality,
+            clients: HashMap::new(),
+            sessions: Sessions::new(),
+            map_links,
+            trades: Trades::default(),
+            link_cables: LinkCables::default(),
+            next_connection: 0,
+            packet_sender,
+        }
+    }
+
+    fn handle_packet(&mut self, packet: ClientPacket) {
+        // Ignore anything sent by a connection that has since been replaced
+        match self.clients.get(&packet.id) {
+            Some(client) if client.connection == packet.connection => {}
+            _ => return,
+        }
+
+        let sender = packet.id;
+        let message = packet.event;
+        match message {
+            NetworkEvent::FullUpdate(_, player) => self.update_player(sender, player),
+
+            NetworkEvent::MovementUpdate(_, movement) => self.update_movement(sender, movement),
+
+            NetworkEvent::MapConnections(map_id, connected_maps) => {
+                if let Some(client) = self.clients.get_mut(&sender) {
+                    client.reported_links = Some((map_id, connected_maps));
+                }
+            }
+
+            // Clients may only disconnect themselves
+            NetworkEvent::PlayerQuit(_) => self.disconnect(sender),
+
+            NetworkEvent::BattleChallenge(..) if !self.config.rules.allow_battles => {}
+            NetworkEvent::TradeOffer(..) if !self.config.rules.allow_trades => {}
+
+            // Players can only send messages and answer challenges and trades for themselves
+            NetworkEvent::Chat(from, _)
+            | NetworkEvent::DirectChat(_, from, _)
+            | NetworkEvent::BattleChallenge(_, from)
+            | NetworkEvent::BattleAccept(_, from)
+            | NetworkEvent::BattleDecline(_, from)
+            | NetworkEvent::BattleCancel(_, from)
+            | NetworkEvent::BattleDataResponse(_, from, _)
+            | NetworkEvent::BattleStart(_, from, ..)
+            | NetworkEvent::BattleAction(_, from, _)
+            | NetworkEvent::TradeOffer(_, from, ..)
+            | NetworkEvent::TradeConfirm(_, from)
+            | NetworkEvent::TradeCancel(_, from)
+            | NetworkEvent::PartyRequest(_, from)
+            | NetworkEvent::PartyResponse(_, from, _)
+            | NetworkEvent::LinkTransfer(_, from, _)
+            | NetworkEvent::LinkReply(_, from, _)
+                if from != sender => {}
+
+            NetworkEvent::Chat(..) => self.broadcast(&message, Some(sender)),
+
+            NetworkEvent::BattleDataResponse(to, _, ref data)
+            | NetworkEvent::BattleStart(to, _, ref data, _) => {
+                if !self.check_party(sender, to, data) {
+                    return;
+                }
+                self.relay(sender, to, &message);
+            }
+
+            NetworkEvent::LinkRequest => {
+                let map_id = match self.clients.get(&sender).and_then(|client| client.movement) {
+                    Some(movement) => movement.map_id,
+                    None => return,
+                };
+                if let Some(partner) = self.link_cables.request(sender, map_id) {
+                    self.send_or_disconnect(sender, &NetworkEvent::LinkOpened(partner));
+                    self.send_or_disconnect(partner, &NetworkEvent::LinkOpened(sender));
+                }
+            }
+
+            // Link cable data only goes to the player on the other end of the cable
+            NetworkEvent::LinkTransfer(to, ..) | NetworkEvent::LinkReply(to, ..)
+                if self.link_cables.partner(sender) == Some(to) =>
+            {
+                self.send_or_disconnect(to, &message);
+            }
+
+            NetworkEvent::LinkClose(_) => {
+                if let Some(partner) = self.link_cables.close(sender) {
+                    self.send_or_disconnect(partner, &NetworkEvent::LinkClose(sender));
+                }
+            }
+
+            NetworkEvent::TradeOffer(to, _, slot, ref pokemon)
+                if self.clients.contains_key(&to) =>
+            {
+                self.trades.offer(sender, to, slot, pokemon.clone());
+                self.send_or_disconnect(to, &message);
+            }
+
+            NetworkEvent::TradeConfirm(to, _) => {
+                for trade in self.trades.confirm(sender, to).into_iter().flatten() {
+                    let Received { player, partner, slot, pokemon } = trade;
+                    let event = NetworkEvent::TradeComplete(partner, slot, pokemon);
+                    self.send_or_disconnect(player, &event);
+                }
+            }
+
+            NetworkEvent::TradeCancel(to, _) => {
+                self.trades.cancel(sender);
+                self.send_or_disconnect(to, &message);
+            }
+
+            NetworkEvent::BattleChallenge(to, _)
+            | NetworkEvent::BattleAccept(to, _)
+            | NetworkEvent::BattleDecline(to, _)
+            | NetworkEvent::BattleCancel(to, _)
+            | NetworkEvent::BattleAction(to, ..)
+            | NetworkEvent::TradeOffer(to, ..)
+            | NetworkEvent::PartyRequest(to, _)
+            | NetworkEvent::PartyResponse(to, ..)
+            | NetworkEvent::DirectChat(to, ..) => self.relay(sender, to, &message),
+
+            // Other events are only ever sent by the server, so ignore them if a client sends
+            // them to us.
+            _ => {}
+        }
+    }
+
+    /// Pass on a message from `sender` to the player `to`
+    fn relay(&mut self, sender: PlayerId, to: PlayerId, message: &NetworkEvent) {
+        if self.clients.contains_key(&to) {
+            self.send_or_disconnect(to, message);
+        }
+        else {
+            // Let the sender know straight away, instead of leaving them waiting for an answer
+            // that will never arrive.
+            self.send_or_disconnect(sender, &NetworkEvent::PlayerUnavailable(to));
+        }
+    }
+
+    /// Check the team that `player` is bringing to a battle against `opponent`. Returns false if
+    /// the battle should not go ahead.
+    fn check_party(&mut self, player: PlayerId, opponent: PlayerId, data: &BattleData) -> bool {
+        let legality = match self.legality.as_ref() {
+            Some(legality) => legality,
+            None => return true,
+        };
+        let reason = match legality.check_battle_data(data) {
+            Ok(()) => return true,
+            Err(illegal) => legality.describe(&illegal),
+        };
+        println!("Player: {} has an illegal team: {}", player, reason);
+
+        if self.config.party_check == PartyCheck::Reject {
+            let event = NetworkEvent::BattleRejected(player, reason.clone());
+            self.send_or_disconnect(opponent, &event);
+            self.send_or_disconnect(player, &NetworkEvent::BattleRejected(opponent, reason));
+            return false;
+        }
+
+        let warning = format!("Your opponent's team is illegal. {}", reason);
+        self.send_or_disconnect(opponent, &NetworkEvent::ServerMessage(warning));
+        true
+    }
+
+    fn add_client(
+        &mut self,
+        mut stream: TcpStream,
+        token: Option<ResumeToken>,
+    ) -> NetworkResult<()> {
+        // Players that are replacing their existing connection do not take up another slot
+        let replacing = token.and_then(|token| self.sessions.player(token));
+        let replacing = replacing.is_some_and(|id| self.clients.contains_key(&id));
+        if self.clients.len() >= self.config.max_players && !replacing {
+            println!("Rejected client: server is full");
+            let message = NetworkEvent::JoinRejected("The server is full".into());
+            let _ = send_to_client(&mut stream, &message);
+            return Ok(());
+        }
+
+        let (id, token) = self.sessions.join(token);
+        if let Err(e) = self.welcome(&mut stream, id, token) {
+            println!("Failed to communicate with client: {}", e);
+            self.sessions.disconnected(id);
+            return Ok(());
+        }
+
+        let connection = self.next_connection;
+        self.next_connection += 1;
+
+        let client = Client {
+            id,
+            connection,
+            client_stream: stream.try_clone()?,
+            server_sender: self.packet_sender.clone(),
+        };
+        thread::spawn(move || {
+            let _ = client_handler(client);
+        });
+
+        println!("New client connected, id: {}", id);
+        let new_client = Connection {
+            connection,
+            client_stream: stream,
+            player: None,
+            movement: None,
+            reported_links: None,
+        };
+        if let Some(old_client) = self.clients.insert(id, new_client) {
+            // The player resumed their session before we noticed that their old connection was
+            // gone.
+            let _ = old_client.client_stream.shutdown(Shutdown::Both);
+        }
+
+        // Nothing else needs to be done here: the new client sends a full update as soon as it
+        // starts, which is treated as them entering the map they are on.
+        Ok(())
+    }
+
+    fn update_player(&mut self, id: PlayerId, player: PlayerData) {
+        let movement = player.movement_data;
+        let previous = match self.clients.get_mut(&id) {
+            Some(client) => {
+                client.player = Some(player.clone());
+                client.movement.replace(movement)
+            }
+            None => return,
+        };
+        self.accept_reported_links(id, movement.map_id);
+        self.relay_movement(id, previous, movement, &NetworkEvent::FullUpdate(id, player));
+    }
+
+    fn update_movement(&mut self, id: PlayerId, movement: MovementData) {
+        let previous = match self.clients.get_mut(&id) {
+            Some(client) => {
+                if let Some(player) = client.player.as_mut() {
+                    player.movement_data = movement;
+                }
+                client.movement.replace(movement)
+            }
+            None => return,
+        };
+        self.accept_reported_links(id, movement.map_id);
+        self.relay_movement(id, previous, movement, &NetworkEvent::MovementUpdate(id, movement));
+    }
+
+    /// Use the map connections reported by a client, if it is now on the map that they were for
+    fn accept_reported_links(&mut self, id: PlayerId, map_id: u8) {
+        let client = match self.clients.get_mut(&id) {
+            Some(client) => client,
+            None => return,
+        };
+        if client.reported_links.as_ref().is_some_and(|(reported, _)| *reported == map_id) {
+            let (map_id, connected_maps) = client.reported_links.take().unwrap();
+            self.map_links.insert(map_id, connected_maps);
+        }
+    }
+
+    /// Forward a player's update to every client that could see them either before or after the
+    /// update. If the player entered a new map, they are also sent the state of everyone that they
+    /// can now see.
+    fn relay_movement(
+        &mut self,
+        id: PlayerId,
+        previous: Option<MovementData>,
+        current: MovementData,
+        message: &NetworkEvent,
+    ) {
+        let full_update = self.clients[&id].player.clone().map(|p| NetworkEvent::FullUpdate(id, p));
+
+        let mut updates = vec![];
+        let mut snapshot = vec![];
+        let links = &self.map_links;
+        for (&other_id, other) in &self.clients {
+            let other_movement = match other.movement {
+                Some(movement) if other_id != id => movement,
+                _ => continue,
+            };
+
+            let visible_before = previous.is_some_and(|p| links.can_see(&other_movement, &p));
+            let visible_now = links.can_see(&other_movement, &current);
+
+            if visible_now && !visible_before {
+                // The other player may have never seen this player before, so they need the full
+                // state, not just the movement.
+                updates.push((other_id, full_update.as_ref().unwrap_or(message)));
+            }
+            else if visible_now || visible_before {
+                updates.push((other_id, message));
+            }
+
+            let was_seen = previous.is_some_and(|p| links.can_see(&p, &other_movement));
+            if links.can_see(&current, &other_movement) && !was_seen {
+                if let Some(player) = other.player.clone() {
+                    snapshot.push(NetworkEvent::FullUpdate(other_id, player));
+                }
+            }
+        }
+
+        for (to, message) in updates {
+            self.send_or_disconnect(to, message);
+        }
+        for message in snapshot {
+            self.send_or_disconnect(id, &message);
+        }
+    }
+
+    /// Send the initial messages to a client that has just joined
+    fn welcome(
+        &self,
+        stream: &mut TcpStream,
+        id: PlayerId,
+        token: ResumeToken,
+    ) -> NetworkResult<()> {
+        send_to_client(stream, &NetworkEvent::PlayerJoin(id, token))?;
+        send_to_client(stream, &NetworkEvent::Rules(self.config.rules.clone()))?;
+        if !self.config.motd.is_empty() {
+            send_to_client(stream, &NetworkEvent::ServerMessage(self.config.motd.clone()))?;
+        }
+        Ok(())
+    }
+
+    /// Send a message to a single client, disconnecting them if the message could not be
+    /// delivered
+    fn send_or_disconnect(&mut self, id: PlayerId, message: &NetworkEvent) {
+        let failed = match self.clients.get_mut(&id) {
+            Some(client) => send_to_client(&mut client.client_stream, message).is_err(),
+            None => false,
+        };
+        if failed {
+            self.disconnect(id);
+        }
+    }
+
+    /// Send a message to every client (except for `skip`), disconnecting any clients that the
+    /// message could not be delivered to.
+    fn broadcast(&mut self, message: &NetworkEvent, skip: Option<PlayerId>) {
+        let mut failed = vec![];
+        for (&client_id, client) in self.clients.iter_mut() {
+            if Some(client_id) == skip {
+                continue;
+            }
+            if send_to_client(&mut client.client_stream, message).is_err() {
+                failed.push(client_id);
+            }
+        }
+
+        for id in failed {
+            self.disconnect(id);
+        }
+    }
+
+    /// Remove a client from the server and notify everyone else that they have left. The client
+    /// can still resume their session until it expires.
+    fn disconnect(&mut self, id: PlayerId) {
+        let client = match self.clients.remove(&id) {
+            Some(client) => client,
+            None => return,
+        };
+
+        // Shutting down the stream ensures that the client's handler thread exits
+        let _ = client.client_stream.shutdown(Shutdown::Both);
+        self.sessions.disconnected(id);
+        self.trades.cancel(id);
+        self.link_cables.close(id);
+        match client.player {
+            Some(player) => {
+                println!("Player: {} ({}) disconnected", id, text::decode(&player.name));
+            }
+            None => println!("Player: {} disconnected", id),
+        }
+
+        self.broadcast(&NetworkEvent::PlayerQuit(id), None);
+    }
+}
+
+/// Read the game's rom if one has been configured, it is required when checking teams
+fn load_rom(config: &ServerConfig) -> Result<Option<Vec<u8>>, String> {
+    let path = match config.rom.as_ref() {
+        Some(path) => path,
+        None if config.party_check == PartyCheck::Off => return Ok(None),
+        None => return Err("Checking teams needs the game's rom, set it with --rom".into()),
+    };
+    let rom = fs::read(path).map_err(|e| format!("failed to read {}: {}", path, e))?;
+
+    // A symbol file next to the rom allows modified roms, the same as for the client
+    let sym_path = Path::new(path).with_extension("sym");
+    let sym_path = if sym_path.exists() { Some(sym_path.as_path()) } else { None };
+    match symbols::select_for_rom(&rom, sym_path) {
+        Ok(Source::Symbols { missing }) if !missing.is_empty() => {
+            println!("Symbols not found, using the built-in addresses instead:");
+            println!("    {}", missing.join(", "));
+        }
+        Ok(_) => {}
+        Err(e) => return Err(format!("cannot use {}: {}", path, e)),
+    }
+    Ok(Some(rom))
+}
+
+fn run_server(
+    config: ServerConfig,
+    legality: Option<Legality>,
+    map_links: MapLinks,
+) -> NetworkResult<()> {
+    let listener = TcpListener::bind(config.listen_addr())?;
+    println!("Listening on {}:{}", config.bind_address, config.port);
 
     let (new_client_sender, new_client_receiver) = crossbeam_channel::unbounded();
     let (packet_sender, packet_receiver) = crossbeam_channel::unbounded();
+    let heartbeat = crossbeam_channel::tick(HEARTBEAT_INTERVAL);
 
+    let storage = Arc::new(SaveStorage::new(&config.save_dir));
     thread::spawn(move || {
-        let _ = acceptor(listener, new_client_sender, packet_sender);
+        let _ = acceptor(listener, new_client_sender, storage);
     });
 
-    let mut clients = HashMap::new();
+    let mut server = Server::new(config, legality, map_links, packet_sender);
     loop {
         crossbeam_channel::select! {
             recv(&packet_receiver) -> player_packet => {
-                let message = player_packet.map_err(|_| NetworkError::RecvError)?;
-                match message {
-                    NetworkEvent::FullUpdate(sender_id, _) |
-                    NetworkEvent::MovementUpdate(sender_id, _) |
-                    NetworkEvent::Chat(sender_id, _) => {
-                        for (&client_id, client_stream) in &mut clients {
-                            if client_id != sender_id {
-                                send_to_client(client_stream, &message).unwrap();
-                            }
-                        }
-                    },
-
-                    NetworkEvent::PlayerQuit(id) => {
-                        clients.remove(&id);
-                        println!("Player: {} disconnected", id);
-                        for (_, client_stream) in &mut clients {
-                            send_to_client(client_stream, &message).unwrap();
-                        }
-                    },
-
-                    NetworkEvent::BattleDataRequest(to, _) |
-                    NetworkEvent::BattleDataResponse(to, _) => {
-                        send_to_client(clients.get_mut(&to).unwrap(), &message).unwrap();
-                    },
-
-                    _ => unimplemented!(),
-                }
+                let packet = player_packet.map_err(|_| NetworkError::RecvError)?;
+                server.handle_packet(packet);
             },
 
             // Handle new clients
             recv(new_client_receiver) -> packet => {
-                let (id, sender) = packet.map_err(|_| NetworkError::RecvError)?;
-                println!("New client connected, id: {}", id);
-                clients.insert(id, sender);
-
-                // Tell connected clients that they need to send an update to the new client
-                for (_, client_stream) in &mut clients {
-                    send_to_client(client_stream, &NetworkEvent::UpdateRequest).unwrap();
-                }
+                let (stream, token) = packet.map_err(|_| NetworkError::RecvError)?;
+                server.add_client(stream, token)?;
+            },
 
+            // Keep idle connections alive, this also lets us detect clients that have gone away
+            // without closing their connection.
+            recv(heartbeat) -> _ => {
+                server.broadcast(&NetworkEvent::Heartbeat, None);
+                server.sessions.expire();
             },
         }
     }
 }
 
-fn send_to_client(client_stream: &mut TcpStream, message: &NetworkEvent) -> io::Result<usize> {
-    let encoded_message = serde_json::to_vec(&message).unwrap();
-    client_stream.write(&encoded_message)?;
-    client_stream.write("\n".as_bytes())
+fn send_to_client(client_stream: &mut TcpStream, message: &NetworkEvent) -> NetworkResult<()> {
+    codec::write_event(client_stream, message)
 }
 
 fn acceptor(
     listener: TcpListener,
-    new_client_sender: crossbeam_channel::Sender<(u32, TcpStream)>,
-    server_sender: crossbeam_channel::Sender<NetworkEvent>,
+    new_client_sender: crossbeam_channel::Sender<(TcpStream, Option<ResumeToken>)>,
+    storage: Arc<SaveStorage>,
 ) -> NetworkResult<()> {
-    let mut next_id = 0;
-
     for stream in listener.incoming() {
         let mut stream = stream?;
-        if let Err(e) = send_to_client(&mut stream, &NetworkEvent::PlayerJoin(next_id)) {
-            println!("Failed to communicate with client: {}", e);
-            continue;
-        }
+        stream.set_read_timeout(Some(IDLE_TIMEOUT))?;
+        stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
 
-        let client = Client {
-            id: next_id,
-            client_stream: stream.try_clone()?,
-            server_sender: server_sender.clone(),
-        };
+        // Perform the handshake on a separate thread so that a slow client cannot stop other
+        // clients from connecting.
+        let new_client_sender = new_client_sender.clone();
+        let storage = storage.clone();
+        thread::spawn(move || match handshake(&mut stream) {
+            Ok(NetworkEvent::JoinRequest(token)) => {
+                let _ = new_client_sender.send((stream, token));
+            }
 
-        thread::spawn(move || {
-            let _ = client_handler(client);
-        });
-        new_client_sender.send((next_id, stream)).map_err(|_| NetworkError::SendError)?;
+            // Save requests are answered straight away without joining the game
+            Ok(request) => {
+                if let Err(e) = storage.handle_request(&mut stream, request) {
+                    println!("Failed to handle save request: {}", e);
+                }
+            }
 
-        next_id += 1;
+            Err(e) => println!("Rejected client: {}", e),
+        });
     }
 
     Ok(())
 }
 
+/// Perform the initial handshake with a client, returning their first request: either to join
+/// the game or to access their save file.
+fn handshake(stream: &mut TcpStream) -> NetworkResult<NetworkEvent> {
+    codec::handshake(stream)?;
+    match codec::read_event(stream)? {
+        request @ (NetworkEvent::JoinRequest(_)
+        | NetworkEvent::SaveDownload(_)
+        | NetworkEvent::SaveUpload(..)) => Ok(request),
+        _ => Err(NetworkError::DecodeError),
+    }
+}
+
 fn client_handler(client: Client) -> NetworkResult<()> {
+    let (id, connection) = (client.id, client.connection);
+    let send = |event| {
+        let packet = ClientPacket { id, connection, event };
+        client.server_sender.send(packet).map_err(|_| NetworkError::SendError)
+    };
+
     let mut client_stream = BufReader::new(client.client_stream);
-    let mut data = String::new();
     loop {
-        match client_stream.read_line(&mut data) {
-            Ok(_) => {
-                let packet = serde_json::from_str(&data).unwrap();
-                client.server_sender.send(packet).map_err(|_| NetworkError::SendError)?;
-            }
+        // Any error here means that the client is gone: either they closed the connection, sent
+        // us garbage, or we have not heard from them (not even a heartbeat) within the timeout.
+        match codec::read_event(&mut client_stream) {
+            Ok(NetworkEvent::Heartbeat) => {}
+
+            Ok(packet) => send(packet)?,
 
-            Err(_) => {
-                let packet = NetworkEvent::PlayerQuit(client.id);
-                client.server_sender.send(packet).map_err(|_| NetworkError::SendError)?;
+            Err(e) => {
+                println!("Lost connection to player {}: {}", id, e);
+                send(NetworkEvent::PlayerQuit(id))?;
                 return Ok(());
             }
         }
-        data.clear();
     }
 }
 
 fn main() {
-    if let Err(e) = run_server("0.0.0.0:8080") {
+    let config = match ServerConfig::from_args(std::env::args().skip(1)) {
+        Ok(config) => config,
+        Err(e) => {
+            println!("{}", e);
+            return;
+        }
+    };
+
+    let rom = match load_rom(&config) {
+        Ok(rom) => rom,
+        Err(e) => {
+            println!("{}", e);
+            return;
+        }
+    };
+
+    // Without a rom, the server relies on clients to report how maps are connected
+    let map_links = rom.as_ref().map(|rom| MapLinks::from_rom(&rom[..])).unwrap_or_default();
+    let legality = match config.party_check {
+        PartyCheck::Off => None,
+        _ => rom.as_ref().map(|rom| Legality::from_rom(&rom[..])),
+    };
+
+    if let Err(e) = run_server(config, legality, map_links) {
         println!("Server failed unexpectedly and had to close.\nReason: {}", e);
     }
 }
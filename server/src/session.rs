@@ -0,0 +1,80 @@
+//! Tracks player sessions, so that a client which loses its connection can resume its previous
+//! session (and keep the same player id) when it reconnects.
+use std::{
+    collections::{hash_map::RandomState, HashMap},
+    hash::{BuildHasher, Hasher},
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+use interface::PlayerId;
+use network_common::{ResumeToken, RESUME_TIMEOUT};
+
+struct Session {
+    id: PlayerId,
+    disconnected_at: Option<Instant>,
+}
+
+pub struct Sessions {
+    next_id: PlayerId,
+    sessions: HashMap<ResumeToken, Session>,
+    /// Randomly keyed hasher used for generating tokens that clients cannot guess
+    token_state: RandomState,
+}
+
+impl Sessions {
+    pub fn new() -> Sessions {
+        Sessions { next_id: 0, sessions: HashMap::new(), token_state: RandomState::new() }
+    }
+
+    /// Start a session for a newly connected client. If the client presented the token of a
+    /// session that has not expired then that session is resumed, otherwise a new session is
+    /// created.
+    pub fn join(&mut self, token: Option<ResumeToken>) -> (PlayerId, ResumeToken) {
+        if let Some(session) = token.and_then(|token| self.sessions.get_mut(&token)) {
+            session.disconnected_at = None;
+            return (session.id, token.unwrap());
+        }
+
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let token = self.new_token(id);
+        self.sessions.insert(token, Session { id, disconnected_at: None });
+        (id, token)
+    }
+
+    /// Get the id of the player that a token belongs to
+    pub fn player(&self, token: ResumeToken) -> Option<PlayerId> {
+        self.sessions.get(&token).map(|session| session.id)
+    }
+
+    /// Mark the session for a player as disconnected, starting the time that they have to resume
+    /// it.
+    pub fn disconnected(&mut self, id: PlayerId) {
+        for session in self.sessions.values_mut().filter(|session| session.id == id) {
+            session.disconnected_at = Some(Instant::now());
+        }
+    }
+
+    /// Remove sessions that have been disconnected for too long to be resumed
+    pub fn expire(&mut self) {
+        self.sessions.retain(|_, session| match session.disconnected_at {
+            Some(time) => time.elapsed() < RESUME_TIMEOUT,
+            None => true,
+        });
+    }
+
+    fn new_token(&self, id: PlayerId) -> ResumeToken {
+        let mut salt = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |t| t.as_nanos());
+        loop {
+            let mut hasher = self.token_state.build_hasher();
+            hasher.write_u32(id);
+            hasher.write_u128(salt);
+            let token = hasher.finish();
+            if !self.sessions.contains_key(&token) {
+                return token;
+            }
+            salt += 1;
+        }
+    }
+}
@@ -0,0 +1,84 @@
+//! Save files stored on the server for each account. Every save is kept in its own file in the
+//! save directory, starting with the save counter followed by the save data.
+use std::{fs, io, net::TcpStream, path::PathBuf, sync::Mutex};
+
+use network_common::{codec, error::NetworkResult, AccountKey, NetworkEvent, RemoteSave};
+
+pub struct SaveStorage {
+    dir: PathBuf,
+    /// Held while a save is being checked and replaced, so that two uploads for the same account
+    /// cannot both be accepted.
+    lock: Mutex<()>,
+}
+
+impl SaveStorage {
+    pub fn new(dir: impl Into<PathBuf>) -> SaveStorage {
+        SaveStorage { dir: dir.into(), lock: Mutex::new(()) }
+    }
+
+    /// Answer a save request sent by a client instead of joining the game
+    pub fn handle_request(
+        &self,
+        stream: &mut TcpStream,
+        request: NetworkEvent,
+    ) -> NetworkResult<()> {
+        let reply = match request {
+            NetworkEvent::SaveDownload(account) => NetworkEvent::SaveData(self.load(account)?),
+            NetworkEvent::SaveUpload(account, previous, save) => {
+                match self.store(account, previous, save)? {
+                    Ok(counter) => NetworkEvent::SaveAccepted(counter),
+                    Err(counter) => NetworkEvent::SaveConflict(counter),
+                }
+            }
+            _ => return Ok(()),
+        };
+        codec::write_event(stream, &reply)
+    }
+
+    fn load(&self, account: AccountKey) -> io::Result<Option<RemoteSave>> {
+        let data = match fs::read(self.path(account)) {
+            Ok(data) => data,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        if data.len() < 8 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "save file is truncated"));
+        }
+
+        let mut counter = [0; 8];
+        counter.copy_from_slice(&data[..8]);
+        Ok(Some(RemoteSave { counter: u64::from_le_bytes(counter), data: data[8..].to_vec() }))
+    }
+
+    /// Store a save if it replaces the save we have (`previous` is the counter of the save that
+    /// it is based on). Returns the counter of the stored save, or the counter of the save we
+    /// already have if the upload conflicts with it.
+    fn store(
+        &self,
+        account: AccountKey,
+        previous: u64,
+        save: RemoteSave,
+    ) -> io::Result<Result<u64, u64>> {
+        let _guard = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+
+        let current = self.load(account)?.map_or(0, |save| save.counter);
+        if current != previous || save.counter <= current {
+            return Ok(Err(current));
+        }
+
+        fs::create_dir_all(&self.dir)?;
+        let mut data = save.counter.to_le_bytes().to_vec();
+        data.extend_from_slice(&save.data);
+
+        // Write to a temporary file first so that a failed write does not lose the old save
+        let path = self.path(account);
+        let tmp_path = path.with_extension("sav.tmp");
+        fs::write(&tmp_path, data)?;
+        fs::rename(&tmp_path, &path)?;
+        Ok(Ok(save.counter))
+    }
+
+    fn path(&self, account: AccountKey) -> PathBuf {
+        self.dir.join(format!("{:016x}.sav", account))
+    }
+}
@@ -0,0 +1,71 @@
+//! Trades between players. The server keeps each player's offer, and only swaps the pokemon once
+//! both players have confirmed the trade, so neither player can change their offer at the last
+//! moment.
+use std::collections::HashMap;
+
+use interface::{data::PokemonRecord, PlayerId};
+
+struct Offer {
+    partner: PlayerId,
+    slot: u8,
+    pokemon: PokemonRecord,
+    confirmed: bool,
+}
+
+/// One side of a completed trade: the pokemon that `player` receives in place of `slot`
+pub struct Received {
+    pub player: PlayerId,
+    pub partner: PlayerId,
+    pub slot: u8,
+    pub pokemon: PokemonRecord,
+}
+
+#[derive(Default)]
+pub struct Trades {
+    offers: HashMap<PlayerId, Offer>,
+}
+
+impl Trades {
+    /// Offer the pokemon in `slot` to `partner`, replacing any earlier offer
+    pub fn offer(&mut self, player: PlayerId, partner: PlayerId, slot: u8, pokemon: PokemonRecord) {
+        self.offers.insert(player, Offer { partner, slot, pokemon, confirmed: false });
+
+        // The partner only agreed to the previous offer
+        if let Some(offer) = self.offers.get_mut(&partner) {
+            if offer.partner == player {
+                offer.confirmed = false;
+            }
+        }
+    }
+
+    /// Confirm the trade with `partner`. Once both players have confirmed, the trade is removed
+    /// and the pokemon that each player receives is returned.
+    pub fn confirm(&mut self, player: PlayerId, partner: PlayerId) -> Option<[Received; 2]> {
+        match self.offers.get_mut(&player) {
+            Some(offer) if offer.partner == partner => offer.confirmed = true,
+            _ => return None,
+        }
+
+        match self.offers.get(&partner) {
+            Some(offer) if offer.partner == player && offer.confirmed => {}
+            _ => return None,
+        }
+
+        let ours = self.offers.remove(&player)?;
+        let theirs = self.offers.remove(&partner)?;
+        Some([
+            Received { player, partner, slot: ours.slot, pokemon: theirs.pokemon },
+            Received { player: partner, partner: player, slot: theirs.slot, pokemon: ours.pokemon },
+        ])
+    }
+
+    /// Cancel any trade that `player` is part of
+    pub fn cancel(&mut self, player: PlayerId) {
+        if let Some(offer) = self.offers.remove(&player) {
+            if self.offers.get(&offer.partner).is_some_and(|theirs| theirs.partner == player) {
+                self.offers.remove(&offer.partner);
+            }
+        }
+        self.offers.retain(|_, offer| offer.partner != player);
+    }
+}